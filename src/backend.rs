@@ -1,60 +1,246 @@
-use anyhow::{Context, Result};
+mod worker;
+
+use anyhow::Result;
 use futures::{
     channel::mpsc,
-    channel::mpsc::{Receiver, Sender},
-    stream, SinkExt, StreamExt,
+    channel::mpsc::{Sender, UnboundedReceiver, UnboundedSender},
+    stream, FutureExt, SinkExt, StreamExt,
 };
 use image::DynamicImage;
-use std::{future::Future, sync::Arc};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-pub use crate::model::ModelType;
-use crate::model::{mock, onnx, DetectionModel, DetectionResults};
+use crate::model::onnx::{yolo, ONNXModel};
+use crate::model::postprocess::PostProcessing;
+use crate::model::registry::{ModelConfig, ModelRegistry};
+use crate::model::{mock, DetectionModel, DetectionResults};
+pub use crate::model::{Device, ModelType};
+use worker::Worker;
 
 impl std::fmt::Display for ModelType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
             Self::Mock => "Mock",
             Self::GroundingDINO => "GroundingDINO",
+            Self::OWLv2 => "OWLv2",
+            Self::Yolo => "YOLO",
+            Self::YoloSeg => "YOLO-seg",
+            Self::YoloPose => "YOLO-pose",
+            Self::RTDETR => "RT-DETR",
         })
     }
 }
 
+impl std::str::FromStr for ModelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mock" => Ok(Self::Mock),
+            "grounding-dino" | "groundingdino" => Ok(Self::GroundingDINO),
+            "owlv2" => Ok(Self::OWLv2),
+            "yolo" => Ok(Self::Yolo),
+            "yolo-seg" => Ok(Self::YoloSeg),
+            "yolo-pose" => Ok(Self::YoloPose),
+            "rt-detr" | "rtdetr" => Ok(Self::RTDETR),
+            _ => Err(format!(
+                "Unknown model '{s}', expected mock, grounding-dino, owlv2, yolo, yolo-seg, yolo-pose or rt-detr"
+            )),
+        }
+    }
+}
+
+/// Identifies an image across a job, so results can be matched up with the image they belong to
+pub type ImageId = u64;
+
+/// Identifies a detection request, so results of a cancelled or outdated one can be told apart
+pub type RequestId = u64;
+
+#[derive(Debug, Clone)]
+pub struct ImageRef {
+    pub id: ImageId,
+    pub path: PathBuf,
+    /// The decoded image if the sender has it, otherwise the backend loads `path`
+    pub image: Option<Arc<DynamicImage>>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Input {
-    ProcessImage(Arc<DynamicImage>),
+    ProcessImage(RequestId, ImageRef),
+    ProcessBatch(RequestId, Vec<ImageRef>),
     SelectModel(ModelType),
+    /// Use other files for a model, reloading it if it's the selected one
+    SetModelConfig(ModelType, ModelConfig),
     UpdateParams(DetectionParams),
-    Stop,
+    /// Only load model weights on the first detection instead of when the model is selected
+    SetLazyLoading(bool),
+    /// Abandon a request, whether it is running or still queued. Results of the image being
+    /// processed are dropped.
+    Cancel(RequestId),
+}
+
+impl Input {
+    /// The detection request this input starts, if any
+    fn request(&self) -> Option<RequestId> {
+        match self {
+            Input::ProcessImage(id, _) | Input::ProcessBatch(id, _) => Some(*id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Output {
     Loading,
-    Ready(Sender<Input>),
-    Progress(f32),
-    Finished(DetectionResults),
-    Error(String),
+    /// Unbounded, so the frontend can send without waiting and inputs keep their order
+    Ready(UnboundedSender<Input>),
+    /// Loading the weights of a model, which can take several seconds
+    ModelLoading {
+        model: ModelType,
+    },
+    ModelLoaded {
+        model: ModelType,
+        load_ms: u64,
+    },
+    /// Loading the weights failed, the error itself is reported by the input that needed them
+    ModelLoadFailed {
+        model: ModelType,
+    },
+    /// Sent after each image of a job, whether it succeeded or not
+    Progress(RequestId, Progress),
+    Finished(RequestId, ImageId, DetectionResults),
+    /// Detection failed for an image of a request, the request goes on with the next image
+    Failed(RequestId, ImageId, BackendError),
+    /// The execution provider the model runs on, sent whenever it changes
+    ActiveDevice(Device),
+    Cancelled(RequestId),
+    Error(BackendError),
+    /// The last output of the stream, the backend has stopped and takes no more inputs
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+    /// Estimated time left, based on the average time per image so far
+    pub eta: Duration,
 }
 
+impl Progress {
+    pub fn is_finished(&self) -> bool {
+        self.done >= self.total
+    }
+}
+
+/// Errors reported to the frontend. The backend keeps running after sending one of these.
 #[derive(Debug, Clone)]
+pub enum BackendError {
+    ModelFileMissing(PathBuf),
+    OrtInit(String),
+    Inference(String),
+    BadParams(String),
+    ModelNotConfigured(ModelType),
+    Worker(String),
+    ImageLoad(PathBuf),
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ModelFileMissing(path) => {
+                write!(f, "Model weights not found at {}", path.display())
+            }
+            Self::OrtInit(error) => write!(f, "Failed to initialize ONNX Runtime: {error}"),
+            Self::Inference(error) => write!(f, "Inference failed: {error}"),
+            Self::BadParams(error) => write!(f, "Invalid detection parameters: {error}"),
+            Self::ModelNotConfigured(model) => write!(f, "No weights configured for {model}"),
+            Self::Worker(error) => write!(f, "Inference worker failed: {error}"),
+            Self::ImageLoad(path) => write!(f, "Failed to load image {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<anyhow::Error> for BackendError {
+    /// Recover a [`BackendError`] raised further down, treating anything else as an inference failure
+    fn from(error: anyhow::Error) -> Self {
+        error
+            .downcast::<BackendError>()
+            .unwrap_or_else(|error| BackendError::Inference(format!("{error:#}")))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct DetectionParams {
-    pub confidence_threshold: f32,
+    /// Minimum box confidence for the predicted class. Applied to the model output, which goes
+    /// down to [`Self::CONFIDENCE_FLOOR`], so it can change without running the model again.
+    pub class_confidence: f32,
+    /// Minimum confidence for a text token to be grounded to a box
+    pub text_confidence: f32,
     pub class_names: Vec<String>,
+    /// Execution provider to run the model on, falling back to CPU if it's unavailable
+    pub device: Device,
+    /// Applied to the detections by the backend, the model never sees these
+    pub post_processing: PostProcessing,
+}
+
+impl DetectionParams {
+    /// Class confidence the models are built with, the lowest the class threshold can go
+    pub const CONFIDENCE_FLOOR: f32 = 0.05;
+
+    /// Whether switching to `other` changes anything the model is built with
+    pub fn model_changed(&self, other: &DetectionParams) -> bool {
+        let DetectionParams {
+            class_confidence: _,
+            text_confidence,
+            class_names,
+            device,
+            post_processing: _,
+        } = self;
+        *text_confidence != other.text_confidence
+            || *class_names != other.class_names
+            || *device != other.device
+    }
 }
 
 impl Default for DetectionParams {
     fn default() -> Self {
         DetectionParams {
-            confidence_threshold: 0.25,
+            class_confidence: 0.25,
+            text_confidence: 0.25,
             class_names: vec!["person".to_string(), "car".to_string(), "bus".to_string()],
+            device: Device::default(),
+            post_processing: PostProcessing::default(),
         }
     }
 }
 
+/// Split prompts given as `cat, dog` on the command line, leaving out blank ones
+pub fn parse_prompts(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|prompt| !prompt.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 struct Backend {
     params: DetectionParams,
-    model: Option<Box<dyn DetectionModel>>,
+    registry: ModelRegistry,
+    /// Owns the model of `selected_model`
+    worker: Worker,
     selected_model: Option<ModelType>,
+    /// Last device reported to the frontend
+    active_device: Option<Device>,
+    lazy_loading: bool,
 }
 
 impl Default for Backend {
@@ -67,64 +253,290 @@ impl Backend {
     pub fn new() -> Self {
         let params = DetectionParams::default();
         Backend {
-            model: None,
+            worker: Worker::spawn(),
             selected_model: None,
+            active_device: None,
+            lazy_loading: false,
             params,
+            registry: ModelRegistry::default(),
         }
     }
 
-    async fn select_model(&mut self, model_type: ModelType) -> Result<()> {
-        // Check if the model is already loaded
+    async fn select_model(
+        &mut self,
+        model_type: ModelType,
+        output: &mut Sender<Output>,
+    ) -> Result<()> {
         if self.selected_model == Some(model_type.clone()) {
-            log::info!("Model {model_type} is already loaded, skipping initialization.");
+            log::info!("Model {model_type} is already selected, skipping initialization.");
+            // Still builds it again if the worker dropped it
+            if !self.lazy_loading {
+                self.load_model(output).await?;
+            }
             return Ok(());
         }
 
-        // Use tokio::task::spawn_blocking for CPU-bound operations
-        // to avoid blocking the async runtime
-        // (probably not necessary after we implemented deferred model loading)
+        self.build_model(model_type.clone()).await?;
+        self.selected_model = Some(model_type);
+
+        if !self.lazy_loading {
+            self.load_model(output).await?;
+        }
+        Ok(())
+    }
+
+    /// Build a model on the worker, which drops the previous one. The weights are loaded later.
+    async fn build_model(&self, model_type: ModelType) -> Result<()> {
         let params = self.params.clone();
-        let model = tokio::task::spawn_blocking(move || {
-            let model: Box<dyn DetectionModel> = match &model_type {
-                ModelType::Mock => Box::new(mock::MockModel::new(&params)),
-                ModelType::GroundingDINO => Box::new(onnx::ONNXModel::new(&params)),
-            };
-            model
-        })
-        .await
-        .context("Failed to load model. Blocking task panicked.")?;
-        self.model = Some(model);
+        let config = self.registry.get(&model_type).cloned();
+        self.worker
+            .run(move |model| {
+                let config = config.as_ref();
+                let built: Box<dyn DetectionModel> = match &model_type {
+                    ModelType::Mock => Box::new(mock::MockModel::new(config, &params)),
+                    ModelType::GroundingDINO => {
+                        Box::new(ONNXModel::<usls::GroundingDINO>::new(config, &params))
+                    }
+                    ModelType::OWLv2 => Box::new(ONNXModel::<usls::OWLv2>::new(config, &params)),
+                    ModelType::Yolo => {
+                        Box::new(ONNXModel::<yolo::YoloDetect>::new(config, &params))
+                    }
+                    ModelType::YoloSeg => {
+                        Box::new(ONNXModel::<yolo::YoloSeg>::new(config, &params))
+                    }
+                    ModelType::YoloPose => {
+                        Box::new(ONNXModel::<yolo::YoloPose>::new(config, &params))
+                    }
+                    ModelType::RTDETR => Box::new(ONNXModel::<usls::RTDETR>::new(config, &params)),
+                };
+                *model = Some(built);
+            })
+            .await?;
         Ok(())
     }
 
-    async fn process_image(
-        &mut self,
-        image_data: &Arc<DynamicImage>,
-        sender: &Sender<Output>,
-    ) -> Result<DetectionResults> {
-        log::info!("Processing image");
-
-        let mut sender = sender.clone();
-        sender.send(Output::Progress(0.3)).await?;
-
-        // Perform detection
-        let results = if let Some(model) = &mut self.model {
-            // Use block_in_place to run the blocking operation
-            // on the current thread to avoid blocking the async runtime
-            let results = tokio::task::block_in_place(|| model.detect(image_data.as_ref()))?;
-            Ok(results)
-        } else {
-            Err(anyhow::anyhow!("Model not initialized"))
+    /// Load the weights of the selected model unless they already are, reporting how long it took.
+    /// A model the worker dropped after a panic is built again first.
+    async fn load_model(&mut self, output: &mut Sender<Output>) -> Result<()> {
+        let Some(model_type) = self.selected_model.clone() else {
+            return Ok(());
+        };
+        let is_loaded = self
+            .worker
+            .run(|model| model.as_ref().map(|model| model.is_loaded()))
+            .await?;
+        match is_loaded {
+            Some(true) => return Ok(()),
+            Some(false) => {}
+            None => {
+                log::warn!("{model_type} was dropped by the inference worker, building it again");
+                self.build_model(model_type.clone()).await?;
+            }
+        }
+
+        output
+            .send(Output::ModelLoading {
+                model: model_type.clone(),
+            })
+            .await
+            .expect("Failed to send model loading");
+        let start = Instant::now();
+        // A panic drops the model, the next detection builds it again
+        let loaded = self
+            .worker
+            .run(|model| match model {
+                Some(model) => model.load().map(|()| model.device()),
+                None => Ok(None),
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|loaded| loaded);
+        let device = match loaded {
+            Ok(device) => device,
+            Err(error) => {
+                output
+                    .send(Output::ModelLoadFailed { model: model_type })
+                    .await
+                    .expect("Failed to send model load failure");
+                return Err(error);
+            }
         };
 
-        sender.send(Output::Progress(0.7)).await?;
-        results
+        let load_ms = start.elapsed().as_millis() as u64;
+        log::info!("Loaded {model_type} in {load_ms} ms");
+        output
+            .send(Output::ModelLoaded {
+                model: model_type,
+                load_ms,
+            })
+            .await
+            .expect("Failed to send model loaded");
+
+        if let Some(device) = device.filter(|device| Some(*device) != self.active_device) {
+            self.active_device = Some(device);
+            output
+                .send(Output::ActiveDevice(device))
+                .await
+                .expect("Failed to send active device");
+        }
+        Ok(())
     }
 
-    fn update_params(&mut self, params: DetectionParams) {
-        // Update detection parameters
-        todo!("Implement parameter update logic");
+    async fn set_model_config(
+        &mut self,
+        model_type: ModelType,
+        config: ModelConfig,
+        output: &mut Sender<Output>,
+    ) -> Result<()> {
+        log::info!("Using {:?} for {model_type}", config);
+        self.registry.set(model_type.clone(), config);
+
+        if self.selected_model == Some(model_type.clone()) {
+            self.selected_model = None;
+            self.select_model(model_type, output).await?;
+        }
+        Ok(())
+    }
+
+    async fn process_image(&self, image: &ImageRef) -> Result<DetectionResults> {
+        log::info!("Processing {}", image.path.display());
+
+        let ImageRef { path, image, .. } = image.clone();
+        self.worker
+            .run(move |model| {
+                let model = model
+                    .as_mut()
+                    .ok_or_else(|| BackendError::BadParams("No model selected".to_string()))?;
+                // Decoded here, so a batch never holds more than the image being processed
+                let image = match image {
+                    Some(image) => image,
+                    None => Arc::new(
+                        crate::io::load_image(&path)
+                            .map_err(|_| BackendError::ImageLoad(path.clone()))?,
+                    ),
+                };
+                let start = Instant::now();
+                let mut results = model.detect(&image)?;
+                results.timings.total = start.elapsed();
+                Ok(results)
+            })
+            .await?
+    }
+
+    /// Run detection on each image in turn, streaming the results.
+    ///
+    /// An [`Input::Cancel`] for this request stops the job, dropping the result of the image
+    /// being processed. Other inputs arriving meanwhile are put in `pending` to be handled after
+    /// the job.
+    async fn process_batch(
+        &mut self,
+        request: RequestId,
+        images: Vec<ImageRef>,
+        output: &mut Sender<Output>,
+        receiver: &mut UnboundedReceiver<Input>,
+        pending: &mut VecDeque<Input>,
+    ) {
+        let total = images.len();
+        let start = Instant::now();
+
+        for (i, image) in images.into_iter().enumerate() {
+            let mut cancelled = false;
+            while let Ok(input) = receiver.try_recv() {
+                cancelled |= defer_input(input, request, pending);
+            }
+
+            // Weights dropped by a parameter change, or never loaded when lazy, load here
+            let results = if cancelled {
+                None
+            } else {
+                match self.load_model(output).await {
+                    Ok(()) => {
+                        let detection = self.process_image(&image);
+                        let (results, cancel) =
+                            keep_receiving(detection, request, receiver, pending).await;
+                        // Nobody wants the results any more
+                        (!cancel).then_some(results)
+                    }
+                    Err(error) => Some(Err(error)),
+                }
+            };
+            let Some(results) = results else {
+                log::info!("Request {request} cancelled after {i} of {total} images");
+                output
+                    .send(Output::Cancelled(request))
+                    .await
+                    .expect("Failed to send cancellation");
+                return;
+            };
+
+            let message = match results {
+                Ok(results) => Output::Finished(request, image.id, results),
+                Err(error) => {
+                    log::error!("Failed to process image: {error:#}");
+                    Output::Failed(request, image.id, error.into())
+                }
+            };
+            output
+                .send(message)
+                .await
+                .expect("Failed to send detection results");
+
+            let done = i + 1;
+            let eta = start.elapsed().mul_f64((total - done) as f64 / done as f64);
+            output
+                .send(Output::Progress(request, Progress { done, total, eta }))
+                .await
+                .expect("Failed to send progress");
+        }
+    }
+
+    async fn update_params(&mut self, params: DetectionParams) -> Result<()> {
+        let model_params = params.clone();
+        self.worker
+            .run(move |model| match model {
+                Some(model) => model.update_params(&model_params),
+                None => Ok(()),
+            })
+            .await??;
         log::info!("Updated detection parameters: {:?}", params);
+        self.params = params;
+        Ok(())
+    }
+}
+
+/// Put an input that arrived during the job `request` aside in `pending`. Returns whether it
+/// cancels the job, a cancel for a queued job drops that job instead.
+fn defer_input(input: Input, request: RequestId, pending: &mut VecDeque<Input>) -> bool {
+    match input {
+        Input::Cancel(id) if id == request => true,
+        Input::Cancel(id) => {
+            pending.retain(|input| input.request() != Some(id));
+            false
+        }
+        input => {
+            pending.push_back(input);
+            false
+        }
+    }
+}
+
+/// Wait for `job` while still taking inputs, so the backend loop isn't stuck on a long detection.
+/// Returns whether the job's request was cancelled meanwhile, see [`defer_input`].
+async fn keep_receiving<T>(
+    job: impl Future<Output = T>,
+    request: RequestId,
+    receiver: &mut UnboundedReceiver<Input>,
+    pending: &mut VecDeque<Input>,
+) -> (T, bool) {
+    let mut job = std::pin::pin!(job);
+    let mut cancelled = false;
+    loop {
+        tokio::select! {
+            result = &mut job => return (result, cancelled),
+            Some(input) = receiver.next() => {
+                cancelled |= defer_input(input, request, pending);
+            }
+        }
     }
 }
 
@@ -134,21 +546,31 @@ impl Backend {
 /// This is a more ergonomic [`stream::unfold`], which allows you to go
 /// from the "world of futures" to the "world of streams" by simply looping
 /// and publishing to an async channel from inside a [`Future`].
+///
+/// A panic in the future ends the stream instead of the task polling it.
 fn channel<T, F>(size: usize, f: impl FnOnce(Sender<T>) -> F) -> impl stream::Stream<Item = T>
 where
     F: Future<Output = ()>,
 {
     let (sender, receiver) = mpsc::channel(size);
 
-    let runner = stream::once(f(sender)).filter_map(|_| async { None });
+    let runner =
+        stream::once(AssertUnwindSafe(f(sender)).catch_unwind()).filter_map(|result| async move {
+            if result.is_err() {
+                log::error!("Backend panicked");
+            }
+            None
+        });
 
     stream::select(receiver, runner)
 }
 
+/// Start a backend. The stream ends with [`Output::Stopped`], whether the backend stopped
+/// because the application dropped its sender or because it panicked.
 pub fn connect() -> impl futures::stream::Stream<Item = Output> {
-    channel(100, |mut output| async move {
+    let backend = channel(100, |mut output| async move {
         // Create channel
-        let (sender, mut receiver) = mpsc::channel(100);
+        let (sender, mut receiver) = mpsc::unbounded();
 
         output
             .send(Output::Loading)
@@ -163,46 +585,79 @@ pub fn connect() -> impl futures::stream::Stream<Item = Output> {
             .await
             .expect("Failed to send sender");
 
+        // Inputs that arrived while a job was running
+        let mut pending = VecDeque::new();
+
         loop {
-            // Read next input sent from `Application`
-            let input = receiver.select_next_some().await;
+            // Read next input sent from `Application`, stopping once all senders are gone
+            let input = match pending.pop_front() {
+                Some(input) => input,
+                None => match receiver.next().await {
+                    Some(input) => input,
+                    None => break,
+                },
+            };
 
             match input {
-                Input::ProcessImage(image) => {
-                    // Do some async work...
-                    let results = backend
-                        .process_image(&image, &output)
-                        .await
-                        .expect("Failed to process image");
-
-                    // Finally, we can optionally produce a message to tell the
-                    // `Application` the work is done
-                    output
-                        .send(Output::Progress(1.0))
-                        .await
-                        .expect("Failed to send progress");
-
-                    output
-                        .send(Output::Finished(results))
-                        .await
-                        .expect("Failed to send detection results");
+                Input::ProcessImage(request, image) => {
+                    backend
+                        .process_batch(
+                            request,
+                            vec![image],
+                            &mut output,
+                            &mut receiver,
+                            &mut pending,
+                        )
+                        .await;
                 }
-                Input::SelectModel(model_type) => {
+                Input::ProcessBatch(request, images) => {
                     backend
-                        .select_model(model_type.clone())
+                        .process_batch(request, images, &mut output, &mut receiver, &mut pending)
+                        .await;
+                }
+                Input::SelectModel(model_type) => {
+                    if let Err(error) = backend.select_model(model_type, &mut output).await {
+                        log::error!("Failed to select model: {error:#}");
+                        output
+                            .send(Output::Error(error.into()))
+                            .await
+                            .expect("Failed to send error");
+                    }
+                }
+                Input::SetModelConfig(model_type, config) => {
+                    if let Err(error) = backend
+                        .set_model_config(model_type, config, &mut output)
                         .await
-                        .expect("Failed to select model");
+                    {
+                        log::error!("Failed to set model config: {error:#}");
+                        output
+                            .send(Output::Error(error.into()))
+                            .await
+                            .expect("Failed to send error");
+                    }
                 }
                 Input::UpdateParams(params) => {
-                    backend.update_params(params);
+                    if let Err(error) = backend.update_params(params).await {
+                        log::error!("Failed to update parameters: {error:#}");
+                        output
+                            .send(Output::Error(error.into()))
+                            .await
+                            .expect("Failed to send error");
+                    }
+                }
+                Input::SetLazyLoading(lazy) => {
+                    log::info!("Lazy model loading: {lazy}");
+                    backend.lazy_loading = lazy;
                 }
-                Input::Stop => {
-                    // Stop processing
-                    break;
+                Input::Cancel(request) => {
+                    log::debug!("Request {request} is not running, dropping it if queued");
+                    pending.retain(|input| input.request() != Some(request));
                 }
             }
         }
-    })
+    });
+
+    backend.chain(stream::once(async { Output::Stopped }))
 }
 
 // fn connect() -> impl futures::stream::Stream<Item = Output> {
@@ -0,0 +1,62 @@
+//! The thread that owns the model and does all the blocking work on it: building, loading
+//! weights and running detections. The async backend loop only hands it jobs and awaits the
+//! replies, so it never blocks the runtime.
+
+use std::panic::{self, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+
+use super::BackendError;
+use crate::model::DetectionModel;
+
+pub type Model = Option<Box<dyn DetectionModel>>;
+
+type Job = Box<dyn FnOnce(&mut Model) + Send>;
+
+pub struct Worker {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Worker {
+    /// Jobs waiting for the worker, sending more waits for room
+    const QUEUE_SIZE: usize = 4;
+
+    /// Start the worker thread. It stops once the worker is dropped and the queue is empty.
+    pub fn spawn() -> Self {
+        let (jobs, mut queue) = mpsc::channel::<Job>(Self::QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("inference".to_string())
+            .spawn(move || {
+                let mut model: Model = None;
+                while let Some(job) = queue.blocking_recv() {
+                    // A panicking model may be left in any state, so drop it and carry on.
+                    // The job's reply is dropped with it, which reports the failure.
+                    if panic::catch_unwind(AssertUnwindSafe(|| job(&mut model))).is_err() {
+                        log::error!("Inference worker job panicked, dropping the model");
+                        model = None;
+                    }
+                }
+                log::debug!("Inference worker stopped");
+            })
+            .expect("Failed to start the inference worker thread");
+        Self { jobs }
+    }
+
+    /// Run `f` with the model on the worker thread and wait for its result
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Model) -> T + Send + 'static,
+    ) -> Result<T, BackendError> {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |model| {
+            // Nobody waiting for the result any more is fine
+            let _ = reply.send(f(model));
+        });
+        self.jobs
+            .send(job)
+            .await
+            .map_err(|_| BackendError::Worker("the worker thread has stopped".to_string()))?;
+        result
+            .await
+            .map_err(|_| BackendError::Worker("the job panicked".to_string()))
+    }
+}
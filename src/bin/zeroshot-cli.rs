@@ -0,0 +1,235 @@
+use anyhow::{bail, Context, Result};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use zeroshot_rust::backend::{
+    self, DetectionParams, Device, ImageId, ImageRef, Input, ModelType, Output, RequestId,
+};
+use zeroshot_rust::export::{self, ExportFormat, ImageDetections};
+use zeroshot_rust::model::registry::ModelRegistry;
+use zeroshot_rust::model::{DetectionResults, Detections};
+use zeroshot_rust::{io, logging};
+
+#[derive(argh::FromArgs)]
+/// Run detection on images without the GUI
+struct Args {
+    /// model to use (mock, grounding-dino, owlv2, yolo, yolo-seg, yolo-pose or rt-detr)
+    #[argh(option, default = "ModelType::GroundingDINO")]
+    model: ModelType,
+
+    /// comma separated text prompts, e.g. "person,car,bus"
+    #[argh(option)]
+    prompts: Option<String>,
+
+    /// confidence threshold for both classes and text
+    #[argh(option)]
+    conf: Option<f32>,
+
+    /// execution provider: cpu, cuda, tensorrt or openvino (falls back to cpu)
+    #[argh(option)]
+    device: Option<Device>,
+
+    /// also export labels as coco, yolo or voc (can be repeated)
+    #[argh(option)]
+    export: Vec<ExportFormat>,
+
+    /// JSON file with the weights to use for each model
+    #[argh(option)]
+    model_config: Option<PathBuf>,
+
+    /// directory to write annotated images and results to
+    #[argh(option, default = "PathBuf::from(\"output\")")]
+    out_dir: PathBuf,
+
+    /// image files or directories of images
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+}
+
+/// Results written next to each annotated image
+#[derive(Serialize)]
+struct ImageResults<'a> {
+    image: &'a Path,
+    width: u32,
+    height: u32,
+    #[serde(flatten)]
+    detections: &'a Detections,
+}
+
+/// Wait for the backend to finish `request`, which covers one image. Errors not tied to a request,
+/// such as a failed eager load, are only reported, the request then fails on its own.
+async fn next_result(
+    outputs: &mut (impl futures::Stream<Item = Output> + Unpin),
+    request: RequestId,
+) -> Result<DetectionResults> {
+    while let Some(output) = outputs.next().await {
+        match output {
+            Output::Finished(id, _, results) if id == request => return Ok(results),
+            Output::Failed(id, _, error) if id == request => return Err(error.into()),
+            Output::Error(error) => eprintln!("Backend error: {error}"),
+            Output::ModelLoaded { model, load_ms } => println!("Loaded {model} in {load_ms} ms"),
+            _ => {}
+        }
+    }
+    bail!("Backend stopped unexpectedly")
+}
+
+fn save_results(
+    path: &Path,
+    stem: &str,
+    image: &image::DynamicImage,
+    results: &DetectionResults,
+    args: &Args,
+    class_names: &[String],
+) -> Result<()> {
+    let out_dir = &args.out_dir;
+
+    let annotated_path = out_dir.join(format!("{stem}.png"));
+    results
+        .annotated
+        .save(&annotated_path)
+        .with_context(|| format!("Failed to save {}", annotated_path.display()))?;
+
+    let json_path = out_dir.join(format!("{stem}.json"));
+    let json = serde_json::to_string_pretty(&ImageResults {
+        image: path,
+        width: image.width(),
+        height: image.height(),
+        detections: &results.detections,
+    })?;
+    std::fs::write(&json_path, json)
+        .with_context(|| format!("Failed to write {}", json_path.display()))?;
+
+    // COCO covers the whole batch, and is written once all images are done
+    let image_detections = ImageDetections {
+        path,
+        width: image.width(),
+        height: image.height(),
+        detections: &results.detections,
+    };
+    for format in args.export.iter().filter(|f| **f != ExportFormat::Coco) {
+        let label_path = out_dir.join(format!("{stem}.{}", format.extension()));
+        export::write(&label_path, *format, &image_detections, class_names)?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init_logging()?;
+    let args: Args = argh::from_env();
+
+    let registry = ModelRegistry::load(args.model_config.as_deref())?;
+    registry
+        .validate(&args.model)
+        .with_context(|| format!("Can not run {}", args.model))?;
+
+    let images = io::collect_images(&args.inputs).context("Failed to list input images")?;
+    if images.is_empty() {
+        bail!("No images given");
+    }
+    let stems = export::file_stems(&images)?;
+    std::fs::create_dir_all(&args.out_dir)
+        .with_context(|| format!("Failed to create {}", args.out_dir.display()))?;
+
+    let mut params = DetectionParams::default();
+    if let Some(prompts) = &args.prompts {
+        params.class_names = backend::parse_prompts(prompts);
+    }
+    if let Some(device) = args.device {
+        params.device = device;
+    }
+    if let Some(conf) = args.conf {
+        params.class_confidence = conf;
+        params.text_confidence = conf;
+    }
+
+    // Drive the backend the same way the GUI does
+    let mut outputs = Box::pin(backend::connect());
+    let mut backend_tx = loop {
+        match outputs.next().await {
+            Some(Output::Ready(tx)) => break tx,
+            Some(_) => continue,
+            None => bail!("Backend stopped before it was ready"),
+        }
+    };
+    for (model, config) in registry.models {
+        backend_tx
+            .send(Input::SetModelConfig(model, config))
+            .await?;
+    }
+    let class_names = match args.model.class_names() {
+        Some(names) => names.iter().map(|name| name.to_string()).collect(),
+        None => params.class_names.clone(),
+    };
+    // The backend returns everything down to the confidence floor, the threshold is ours to apply
+    let confidence = params.class_confidence;
+    backend_tx.send(Input::UpdateParams(params)).await?;
+    backend_tx
+        .send(Input::SelectModel(args.model.clone()))
+        .await?;
+
+    let mut coco_images = Vec::new();
+    let mut failed = 0;
+    for (i, path) in images.iter().enumerate() {
+        println!("[{}/{}] {}", i + 1, images.len(), path.display());
+
+        let Ok(image) = io::load_image(path) else {
+            eprintln!("Failed to load {}", path.display());
+            failed += 1;
+            continue;
+        };
+        let image = Arc::new(image);
+        let request = i as RequestId;
+        backend_tx
+            .send(Input::ProcessImage(
+                request,
+                ImageRef {
+                    id: i as ImageId,
+                    path: path.clone(),
+                    image: Some(image.clone()),
+                },
+            ))
+            .await?;
+
+        let saved = match next_result(&mut outputs, request).await {
+            Ok(mut results) => {
+                results.detections = results.detections.filter_confidence(confidence);
+                let saved = save_results(path, &stems[i], &image, &results, &args, &class_names);
+                coco_images.push((path, image.width(), image.height(), results.detections));
+                saved
+            }
+            Err(error) => Err(error),
+        };
+        if let Err(error) = saved {
+            eprintln!("Failed to process {}: {error:#}", path.display());
+            failed += 1;
+        }
+    }
+
+    // The backend stops once it has no senders left
+    drop(backend_tx);
+
+    if args.export.contains(&ExportFormat::Coco) {
+        let images = coco_images
+            .iter()
+            .map(|(path, width, height, detections)| ImageDetections {
+                path,
+                width: *width,
+                height: *height,
+                detections,
+            })
+            .collect::<Vec<_>>();
+        let coco_path = args.out_dir.join("annotations.json");
+        std::fs::write(&coco_path, export::to_coco(&images, &class_names)?)
+            .with_context(|| format!("Failed to write {}", coco_path.display()))?;
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} images failed", images.len());
+    }
+    Ok(())
+}
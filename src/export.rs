@@ -0,0 +1,403 @@
+//! Export detections to common annotation formats, for using zero-shot output as pre-labels
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::model::{BoundingBox, Detections};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A single COCO JSON file covering all images
+    Coco,
+    /// One normalized YOLO `.txt` label file per image
+    Yolo,
+    /// One Pascal VOC `.xml` file per image
+    Voc,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [ExportFormat::Coco, ExportFormat::Yolo, ExportFormat::Voc];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Coco => "json",
+            Self::Yolo => "txt",
+            Self::Voc => "xml",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Coco => "COCO JSON",
+            Self::Yolo => "YOLO",
+            Self::Voc => "Pascal VOC",
+        })
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "coco" => Ok(Self::Coco),
+            "yolo" => Ok(Self::Yolo),
+            "voc" => Ok(Self::Voc),
+            _ => Err(format!("Unknown format '{s}', expected coco, yolo or voc")),
+        }
+    }
+}
+
+/// Detections for a single image, with what the exporters need to know about the image
+#[derive(Debug, Clone, Copy)]
+pub struct ImageDetections<'a> {
+    pub path: &'a Path,
+    pub width: u32,
+    pub height: u32,
+    pub detections: &'a Detections,
+}
+
+/// Index of the box' class in the prompt list, warning about boxes we can not label
+fn class_index(class_names: &[String], bbox: &BoundingBox) -> Option<usize> {
+    let index = class_names.iter().position(|name| *name == bbox.class);
+    if index.is_none() {
+        log::warn!(
+            "Skipping box with class '{}' not in the prompt list",
+            bbox.class
+        );
+    }
+    index
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct CocoDataset {
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    categories: Vec<CocoCategory>,
+}
+
+#[derive(Serialize)]
+struct CocoImage {
+    id: usize,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: usize,
+    image_id: usize,
+    category_id: usize,
+    bbox: [f32; 4],
+    area: f32,
+    iscrowd: u8,
+    score: f32,
+}
+
+#[derive(Serialize)]
+struct CocoCategory {
+    id: usize,
+    name: String,
+    supercategory: String,
+}
+
+/// COCO dataset JSON, with one category per prompt (ids start at 1)
+pub fn to_coco(images: &[ImageDetections], class_names: &[String]) -> Result<String> {
+    let categories = class_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| CocoCategory {
+            id: i + 1,
+            name: name.clone(),
+            supercategory: "none".to_string(),
+        })
+        .collect();
+
+    let mut annotations = Vec::new();
+    for (image_id, image) in images.iter().enumerate() {
+        for bbox in &image.detections.boxes {
+            let Some(index) = class_index(class_names, bbox) else {
+                continue;
+            };
+            annotations.push(CocoAnnotation {
+                id: annotations.len() + 1,
+                image_id: image_id + 1,
+                category_id: index + 1,
+                bbox: [bbox.x, bbox.y, bbox.width, bbox.height],
+                area: bbox.width * bbox.height,
+                iscrowd: 0,
+                score: bbox.confidence,
+            });
+        }
+    }
+
+    let images = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| CocoImage {
+            id: i + 1,
+            file_name: file_name(image.path),
+            width: image.width,
+            height: image.height,
+        })
+        .collect();
+
+    let dataset = CocoDataset {
+        images,
+        annotations,
+        categories,
+    };
+    serde_json::to_string_pretty(&dataset).context("Failed to serialize COCO dataset")
+}
+
+/// YOLO labels, one `class cx cy w h` line per box with coordinates normalized to the image size
+pub fn to_yolo(image: &ImageDetections, class_names: &[String]) -> String {
+    let width = image.width as f32;
+    let height = image.height as f32;
+
+    image
+        .detections
+        .boxes
+        .iter()
+        .filter_map(|bbox| {
+            let index = class_index(class_names, bbox)?;
+            Some(format!(
+                "{} {:.6} {:.6} {:.6} {:.6}\n",
+                index,
+                (bbox.x + bbox.width / 2.0) / width,
+                (bbox.y + bbox.height / 2.0) / height,
+                bbox.width / width,
+                bbox.height / height,
+            ))
+        })
+        .collect()
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Pascal VOC annotation XML
+pub fn to_voc(image: &ImageDetections) -> String {
+    let folder = image
+        .path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut xml = String::from("<annotation>\n");
+    xml += &format!("  <folder>{}</folder>\n", escape_xml(&folder));
+    xml += &format!(
+        "  <filename>{}</filename>\n",
+        escape_xml(&file_name(image.path))
+    );
+    xml += &format!(
+        "  <path>{}</path>\n",
+        escape_xml(&image.path.to_string_lossy())
+    );
+    xml += &format!(
+        "  <size>\n    <width>{}</width>\n    <height>{}</height>\n    <depth>3</depth>\n  </size>\n",
+        image.width, image.height
+    );
+    xml += "  <segmented>0</segmented>\n";
+    for bbox in &image.detections.boxes {
+        xml += "  <object>\n";
+        xml += &format!("    <name>{}</name>\n", escape_xml(&bbox.class));
+        xml += "    <pose>Unspecified</pose>\n    <truncated>0</truncated>\n    <difficult>0</difficult>\n";
+        xml += &format!(
+            "    <bndbox>\n      <xmin>{}</xmin>\n      <ymin>{}</ymin>\n      <xmax>{}</xmax>\n      <ymax>{}</ymax>\n    </bndbox>\n",
+            bbox.x.round() as i64,
+            bbox.y.round() as i64,
+            (bbox.x + bbox.width).round() as i64,
+            (bbox.y + bbox.height).round() as i64,
+        );
+        xml += "  </object>\n";
+    }
+    xml += "</annotation>\n";
+    xml
+}
+
+/// Base name of the files written for each image, the file stem with a `-2`, `-3`… suffix when
+/// images in different directories or with different extensions share it
+pub fn file_stems(images: &[PathBuf]) -> Result<Vec<String>> {
+    let mut used = HashSet::new();
+    let mut stems = Vec::with_capacity(images.len());
+    for path in images {
+        let stem = path
+            .file_stem()
+            .with_context(|| format!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let mut unique = stem.clone();
+        let mut n = 1;
+        while !used.insert(unique.clone()) {
+            n += 1;
+            unique = format!("{stem}-{n}");
+        }
+        if unique != stem {
+            log::warn!(
+                "Writing the results of {} as {unique}, {stem} is already used",
+                path.display()
+            );
+        }
+        stems.push(unique);
+    }
+    Ok(stems)
+}
+
+/// Write the detections for a single image in the given format
+pub fn write(
+    path: &Path,
+    format: ExportFormat,
+    image: &ImageDetections,
+    class_names: &[String],
+) -> Result<()> {
+    let contents = match format {
+        ExportFormat::Coco => to_coco(std::slice::from_ref(image), class_names)?,
+        ExportFormat::Yolo => to_yolo(image, class_names),
+        ExportFormat::Voc => to_voc(image),
+    };
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(class: &str, x: f32, y: f32, width: f32, height: f32) -> BoundingBox {
+        BoundingBox {
+            class: class.to_string(),
+            confidence: 0.5,
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn fixture() -> Detections {
+        Detections {
+            boxes: vec![
+                bbox("cat", 10.0, 20.0, 30.0, 40.0),
+                bbox("bird", 0.0, 0.0, 5.0, 5.0),
+                bbox("dog", 0.0, 0.0, 200.0, 100.0),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn class_names() -> Vec<String> {
+        vec!["dog".to_string(), "cat".to_string()]
+    }
+
+    fn image<'a>(path: &'a Path, detections: &'a Detections) -> ImageDetections<'a> {
+        ImageDetections {
+            path,
+            width: 200,
+            height: 100,
+            detections,
+        }
+    }
+
+    #[test]
+    fn coco_ids_start_at_one() {
+        let detections = fixture();
+        let images = [
+            image(Path::new("images/a.jpg"), &detections),
+            image(Path::new("images/b.jpg"), &detections),
+        ];
+        let json: serde_json::Value =
+            serde_json::from_str(&to_coco(&images, &class_names()).unwrap()).unwrap();
+
+        let categories = json["categories"].as_array().unwrap();
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories[0]["id"], 1);
+        assert_eq!(categories[0]["name"], "dog");
+        assert_eq!(categories[1]["id"], 2);
+
+        let images = json["images"].as_array().unwrap();
+        assert_eq!(images[0]["id"], 1);
+        assert_eq!(images[0]["file_name"], "a.jpg");
+        assert_eq!(images[1]["id"], 2);
+
+        // The bird is not a prompt, so each image has two annotations
+        let annotations = json["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 4);
+        let ids = annotations.iter().map(|annotation| &annotation["id"]);
+        assert_eq!(ids.collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(annotations[0]["image_id"], 1);
+        assert_eq!(annotations[0]["category_id"], 2);
+        assert_eq!(
+            annotations[0]["bbox"],
+            serde_json::json!([10.0, 20.0, 30.0, 40.0])
+        );
+        assert_eq!(annotations[0]["area"], 1200.0);
+        assert_eq!(annotations[1]["category_id"], 1);
+        assert_eq!(annotations[2]["image_id"], 2);
+    }
+
+    #[test]
+    fn yolo_is_normalized() {
+        let detections = fixture();
+        let labels = to_yolo(&image(Path::new("a.jpg"), &detections), &class_names());
+        assert_eq!(
+            labels,
+            "1 0.125000 0.400000 0.150000 0.400000\n0 0.500000 0.500000 1.000000 1.000000\n"
+        );
+    }
+
+    #[test]
+    fn yolo_without_known_classes_is_empty() {
+        let detections = fixture();
+        assert_eq!(to_yolo(&image(Path::new("a.jpg"), &detections), &[]), "");
+    }
+
+    #[test]
+    fn voc_corners() {
+        let detections = Detections {
+            boxes: vec![bbox("cat", 10.4, 20.6, 30.0, 40.0)],
+            ..Default::default()
+        };
+        let xml = to_voc(&image(Path::new("images/a.jpg"), &detections));
+        assert!(xml.contains("<folder>images</folder>"));
+        assert!(xml.contains("<filename>a.jpg</filename>"));
+        assert!(xml.contains("<width>200</width>\n    <height>100</height>"));
+        assert!(xml.contains("<name>cat</name>"));
+        assert!(xml.contains(
+            "<xmin>10</xmin>\n      <ymin>21</ymin>\n      <xmax>40</xmax>\n      <ymax>61</ymax>"
+        ));
+    }
+
+    #[test]
+    fn voc_escapes_xml() {
+        let detections = Detections {
+            boxes: vec![bbox("salt & <pepper>", 0.0, 0.0, 1.0, 1.0)],
+            ..Default::default()
+        };
+        let xml = to_voc(&image(Path::new("tom's \"cats\".jpg"), &detections));
+        assert!(xml.contains("<name>salt &amp; &lt;pepper&gt;</name>"));
+        assert!(xml.contains("<filename>tom&apos;s &quot;cats&quot;.jpg</filename>"));
+    }
+}
@@ -1,33 +1,123 @@
 use crate::backend::Input;
 use crate::io;
+use crate::model::postprocess::{self, Nms};
+use crate::model::registry::{ModelConfig, ModelRegistry};
+use crate::model::{BoundingBox, DType};
+use crate::screen::inference::annotations::BoxEdit;
 use crate::screen::{inference, Screen};
 use crate::{backend, screen};
 
-use futures::{SinkExt, Stream, StreamExt};
-use iced::{Element, Subscription, Task};
-use image::DynamicImage;
-use std::sync::Arc;
+use futures::{Stream, StreamExt};
+use iced::{keyboard, Element, Subscription, Task};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 pub const DEFAULT_IMAGE: &[u8] =
     include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/bus.jpg"));
 
+/// A backend stopping within this time after a restart was likely brought down by what was
+/// restored, so the next restart leaves the model out
+const RESTART_GRACE: Duration = Duration::from_secs(10);
+
 // #[derive(Default)]
 pub struct ZeroShotRust {
     screen: Screen,
-    pub image: Option<Arc<DynamicImage>>,
-    pub backend_tx: Option<futures::channel::mpsc::Sender<backend::Input>>,
+    pub images: Vec<inference::ImageEntry>,
+    /// Makes the thumbnails of the opened images, aborted when others are opened
+    thumbnail_task: Option<iced::task::Handle>,
+    pub thumbnails_left: usize,
+    pub current_image: Option<usize>,
+    pub backend_tx: Option<futures::channel::mpsc::UnboundedSender<backend::Input>>,
+    /// Bumped on each restart of the backend, which starts a new subscription
+    pub backend_generation: u64,
+    pub backend_restarted: Option<Instant>,
+    /// The parameters the backend has, to tell which changes need the model rebuilt
+    sent_params: Option<backend::DetectionParams>,
+    /// Detect on all images once both the images and the backend are ready, set by `--run`
+    pub pending_run: bool,
+    pub registry: ModelRegistry,
     pub inference_state: screen::inference::InferenceState,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    Detect(Arc<DynamicImage>),
+    Detect,
+    DetectAll,
+    CancelDetection,
     LoadImage,
-    ImageLoaded(Result<image::DynamicImage, io::LoadError>),
-    Backend(backend::Output),
+    LoadFolder,
+    ImagesLoaded(Result<Vec<inference::ImageEntry>, io::LoadError>),
+    ThumbnailLoaded(backend::ImageId, Option<inference::Thumbnail>),
+    /// The full image of an entry, decoded to be shown
+    ImageDecoded(
+        backend::ImageId,
+        Result<inference::DecodedImage, io::LoadError>,
+    ),
+    SelectImage(usize),
+    NextImage,
+    PreviousImage,
+    /// Output of the backend of the given generation
+    Backend(u64, backend::Output),
     DetectionStarted,
     DetectionFinished,
     SelectModel(backend::ModelType),
+    SelectDevice(backend::Device),
+    ToggleLazyLoading(bool),
+    ToggleHud(bool),
+    ToggleAnnotated(bool),
+    /// The annotated image of an image's results, ready for display
+    AnnotatedDisplayed(backend::ImageId, inference::DisplayImage),
+    Zoom(inference::ZoomMode),
+    /// A click on the image, on a box or on the background
+    DetectionClicked(Option<usize>),
+    /// A click on a row in the detection table
+    SelectDetection(usize),
+    SortDetections(inference::SortKey),
+    /// Show or hide a class in the legend
+    ToggleClass(String, bool),
+    /// Show one class alone, or all the visible ones again
+    ShowOnlyClass(Option<String>),
+    ToggleEditing(bool),
+    /// A new box drawn on the image in edit mode, in image pixels
+    BoxDrawn(iced::Rectangle),
+    /// A box moved or resized in edit mode
+    BoxChanged(usize, iced::Rectangle),
+    RelabelSelected(String),
+    DeleteSelected,
+    Undo,
+    Redo,
+    DiscardEdits,
+    /// Redraw the loading spinner
+    AnimationTick,
+    SelectDType(DType),
+    BrowseWeights,
+    WeightsSelected(Result<PathBuf, io::LoadError>),
+    AddPrompt,
+    EditPrompt(usize, String),
+    SubmitParams,
+    RemovePrompt(usize),
+    MovePromptUp(usize),
+    MovePromptDown(usize),
+    SetClassConfidence(f32),
+    SetTextConfidence(f32),
+    SetNms(Nms),
+    SetIouThreshold(f32),
+    SetMinArea(String),
+    SetMaxArea(String),
+    SetTopK(String),
+    SetSynonyms(String),
+    ThresholdsReleased,
+
+    SaveDetections,
+    /// Export the detections of every image with results, e.g. after running on all of them
+    SaveAllDetections,
+    SaveAnnotated,
+    AnnotatedSaved(Result<PathBuf, io::SaveError>),
+    ToggleAutosave(bool),
+    AutosaveFolderSelected(Result<PathBuf, io::LoadError>),
+    DetectionsSaved(Result<PathBuf, io::SaveError>),
+    DismissError,
+    DismissNotice,
 
     GoToScreen(Screen),
 }
@@ -36,56 +126,285 @@ impl Default for ZeroShotRust {
     fn default() -> Self {
         Self {
             screen: Screen::Loading,
-            image: None,
+            images: vec![],
+            thumbnail_task: None,
+            thumbnails_left: 0,
+            current_image: None,
             backend_tx: None,
+            backend_generation: 0,
+            backend_restarted: None,
+            sent_params: None,
+            pending_run: false,
+            registry: ModelRegistry::default(),
             inference_state: screen::inference::InferenceState::default(),
         }
     }
 }
 
+/// What the frontend did about the backend stopping unexpectedly
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendNotice {
+    /// Started again, with the previous model and parameters
+    Restarted,
+    /// Stopped again soon after a restart, so it was started once more without this model
+    RestartedWithoutModel(backend::ModelType),
+    /// Stopped again soon after a restart without a model, so it was left stopped
+    Stopped,
+}
+
+impl std::fmt::Display for BackendNotice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Restarted => f.write_str("The backend stopped unexpectedly and was restarted"),
+            Self::RestartedWithoutModel(model) => write!(
+                f,
+                "The backend stopped again with {model} selected and was restarted without a model"
+            ),
+            Self::Stopped => f.write_str(
+                "The backend keeps stopping unexpectedly, restart the application to try again",
+            ),
+        }
+    }
+}
+
+/// What to set up on startup, from the command line
+#[derive(Debug, Clone, Default)]
+pub struct Startup {
+    /// Image files or directories of images to open
+    pub images: Vec<PathBuf>,
+    pub model: Option<backend::ModelType>,
+    pub prompts: Option<Vec<String>>,
+    /// Confidence threshold for both classes and text
+    pub confidence: Option<f32>,
+    /// Detect on all the opened images as soon as possible
+    pub run: bool,
+}
+
+/// Parse a number typed into an optional field, where blank means none
+fn parse_optional<T: std::str::FromStr>(input: &str) -> Result<Option<T>, T::Err> {
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(None)
+    } else {
+        input.parse().map(Some)
+    }
+}
+
 impl ZeroShotRust {
-    pub fn new() -> (Self, Task<Message>) {
-        (
-            Self {
-                ..Default::default()
-            },
-            Task::perform(
-                async {
-                    // Wait for some time to simulate loading
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    // image::load_from_memory(DEFAULT_IMAGE)
-                    Err(io::LoadError::FileError)
-                },
-                |image| {
-                    if let Ok(image) = image {
-                        Message::ImageLoaded(Ok(image))
-                    } else {
-                        Message::ImageLoaded(Err(io::LoadError::FileError))
-                    }
-                },
-            )
-            .chain(Task::done(Message::GoToScreen(Screen::Inference))),
-        )
+    /// The model and parameters from `startup` reach the backend once it is ready
+    pub fn new(registry: ModelRegistry, startup: Startup) -> (Self, Task<Message>) {
+        let mut app = Self {
+            registry,
+            ..Default::default()
+        };
+        app.refresh_models();
+
+        let state = &mut app.inference_state;
+        if let Some(model) = startup.model {
+            if let Err(problem) = app.registry.validate(&model) {
+                log::warn!("Selected {model} on the command line, but: {problem}");
+            }
+            state.selected_model = Some(model);
+        }
+        if let Some(prompts) = startup.prompts {
+            state.params.class_names = prompts;
+        }
+        if let Some(confidence) = startup.confidence {
+            state.params.class_confidence = confidence;
+            state.params.text_confidence = confidence;
+        }
+
+        if startup.images.is_empty() {
+            if startup.run {
+                log::warn!("Nothing to detect on, no images were given");
+            }
+            return (app, Task::none());
+        }
+        app.pending_run = startup.run;
+        let task = app.load_images(io::open_paths(startup.images));
+        (app, task)
     }
 
     pub fn title(&self) -> String {
         "ZeroShotRust".to_string()
     }
 
-    fn send_to_backend(&self, message: backend::Input) {
-        if let Some(tx) = self.backend_tx.clone() {
-            let mut tx = tx.clone();
-            tokio::spawn(async move {
-                if let Err(e) = tx.send(message).await {
-                    eprintln!("Failed to send message to backend: {:?}", e);
+    /// Check the configured weights for each model, so problems show up in the model picker
+    fn refresh_models(&mut self) {
+        self.inference_state.models = backend::ModelType::ALL
+            .into_iter()
+            .map(|model| {
+                let problem = self.registry.validate(&model).err();
+                if let Some(problem) = &problem {
+                    log::warn!("{model}: {problem}");
                 }
-            });
+                inference::ModelChoice {
+                    model,
+                    problem: problem.map(|problem| problem.to_string()),
+                }
+            })
+            .collect();
+    }
+
+    pub fn current_image(&self) -> Option<&inference::ImageEntry> {
+        self.current_image.and_then(|index| self.images.get(index))
+    }
+
+    /// Show another of the opened images, along with its cached results if any. Returns the
+    /// task decoding it.
+    fn select_image(&mut self, index: usize) -> Task<Message> {
+        if self.current_image == Some(index) || index >= self.images.len() {
+            return Task::none();
+        }
+        // A detection of the image we leave is no use any more, unlike one of all images
+        if self
+            .inference_state
+            .request
+            .is_some_and(|request| !request.batch)
+        {
+            self.cancel_detection();
+        }
+        // Only the image shown is kept decoded, along with its annotated image
+        if let Some(previous) = self.current_image.and_then(|i| self.images.get_mut(i)) {
+            previous.decoded = None;
+            if let Some(results) = &mut previous.results {
+                results.annotated = None;
+            }
+        }
+        let entry = &self.images[index];
+
+        self.inference_state.image = inference::Image::new(None, entry.size());
+        self.inference_state
+            .image
+            .set_show_annotated(self.inference_state.show_annotated);
+        self.inference_state
+            .image
+            .set_editing(self.inference_state.editing);
+        self.inference_state
+            .image
+            .set_class_filter(self.inference_state.class_filter.clone());
+        self.inference_state.image_id = Some(entry.id);
+        match entry.results.clone() {
+            Some(results) => self.inference_state.set_results(results),
+            None => self.inference_state.clear_results(),
+        }
+        self.current_image = Some(index);
+
+        let id = entry.id;
+        Task::perform(
+            inference::DecodedImage::load(entry.path.clone()),
+            move |decoded| Message::ImageDecoded(id, decoded),
+        )
+    }
+
+    /// List the images to open in the background, they are decoded later
+    fn load_images(
+        &mut self,
+        open: impl std::future::Future<Output = Result<Vec<PathBuf>, io::LoadError>> + Send + 'static,
+    ) -> Task<Message> {
+        self.inference_state.selecting_image = true;
+        Task::perform(
+            async move {
+                let paths = open.await?;
+                Ok(paths.into_iter().map(inference::ImageEntry::new).collect())
+            },
+            Message::ImagesLoaded,
+        )
+    }
+
+    /// Detect on all images if asked to on startup and both the images and the backend are ready
+    fn start_pending_run(&mut self) -> Task<Message> {
+        if !self.pending_run || self.backend_tx.is_none() || self.images.is_empty() {
+            return Task::none();
+        }
+        self.pending_run = false;
+        Task::done(Message::DetectAll)
+    }
+
+    /// Abandon the running detection, its results would be outdated
+    fn cancel_detection(&mut self) {
+        if let Some(request) = self.inference_state.request.take() {
+            log::info!("Cancelling detection {}", request.id);
+            self.send_to_backend(Input::Cancel(request.id));
+            self.inference_state.busy = false;
+            self.inference_state.progress = None;
+        }
+    }
+
+    /// Start a new backend after the current one stopped, which restores the selected model and
+    /// parameters once it is ready. Whatever it was doing is lost.
+    fn restart_backend(&mut self) {
+        let crashed_again = self
+            .backend_restarted
+            .is_some_and(|restarted| restarted.elapsed() < RESTART_GRACE);
+        self.backend_tx = None;
+        self.sent_params = None;
+        self.inference_state.busy = false;
+        self.inference_state.request = None;
+        self.inference_state.progress = None;
+        self.inference_state.loading_model = None;
+        self.inference_state.active_device = None;
+
+        let notice = match (crashed_again, self.inference_state.selected_model.take()) {
+            (false, model) => {
+                self.inference_state.selected_model = model;
+                BackendNotice::Restarted
+            }
+            (true, Some(model)) => BackendNotice::RestartedWithoutModel(model),
+            (true, None) => {
+                log::error!("Backend stopped again right after a restart, giving up");
+                self.inference_state.notice = Some(BackendNotice::Stopped);
+                return;
+            }
+        };
+        log::warn!("Restarting the backend: {notice}");
+        self.inference_state.notice = Some(notice);
+        self.backend_generation += 1;
+        self.backend_restarted = Some(Instant::now());
+    }
+
+    /// Send prompts or post-processing typed but not submitted, before they are needed
+    fn send_edited_params(&mut self) {
+        if self.inference_state.params_edited {
+            self.send_params();
+        }
+    }
+
+    /// Send the current parameters to the backend, leaving out blank prompts. Cancels the running
+    /// detection if the model changes with them. The class threshold is applied here and
+    /// post-processing from the next detection, so they don't.
+    fn send_params(&mut self) {
+        self.inference_state.params_edited = false;
+        let mut params = self.inference_state.params.clone();
+        params.class_names = self.inference_state.class_names();
+        if self
+            .sent_params
+            .as_ref()
+            .is_none_or(|sent| params.model_changed(sent))
+        {
+            self.cancel_detection();
+        }
+        self.sent_params = Some(params.clone());
+        self.send_to_backend(Input::UpdateParams(params));
+    }
+
+    /// Send right away rather than from a task, so the backend gets inputs in the order sent
+    fn send_to_backend(&mut self, message: backend::Input) {
+        let Some(tx) = &self.backend_tx else {
+            return;
+        };
+        if let Err(error) = tx.unbounded_send(message) {
+            log::error!("Backend is gone, dropping {:?}", error.into_inner());
+            self.restart_backend();
         }
     }
 
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::Backend(output) => match output {
+            Message::Backend(generation, _) if generation != self.backend_generation => {
+                // Left over from a backend that was replaced
+            }
+            Message::Backend(_, output) => match output {
                 backend::Output::Loading => {
                     log::info!("Backend is loading...");
                     // self.inference_state.model_info = Some("Loading model...".to_string());
@@ -93,72 +412,525 @@ impl ZeroShotRust {
                 backend::Output::Ready(tx) => {
                     log::info!("Backend is ready!");
                     self.backend_tx = Some(tx.clone());
+                    self.send_to_backend(Input::SetLazyLoading(self.inference_state.lazy_loading));
+                    for (model, config) in self.registry.models.clone() {
+                        self.send_to_backend(Input::SetModelConfig(model, config));
+                    }
+                    // Only differs from the defaults after a restart
+                    self.send_params();
+                    if let Some(model) = self.inference_state.selected_model.clone() {
+                        self.send_to_backend(Input::SelectModel(model));
+                    }
                     // self.inference_state.model_info = None;
                     self.screen = Screen::Inference;
+                    return self.start_pending_run();
                 }
-                backend::Output::Progress(progress) => {
-                    if progress >= 1.0 {
+                backend::Output::Progress(request, progress) => {
+                    if !self.inference_state.is_current(request) {
+                        return Task::none();
+                    }
+                    self.inference_state.progress = Some(progress);
+                    if progress.is_finished() {
                         return Task::done(Message::DetectionFinished);
                     };
                 }
-                backend::Output::Finished(results) => {
-                    log::info!("Detection finished!");
-                    todo!("Handle detection results");
+                backend::Output::Cancelled(request) => {
+                    log::info!("Detection {request} cancelled");
+                    if self.inference_state.is_current(request) {
+                        return Task::done(Message::DetectionFinished);
+                    }
+                }
+                backend::Output::Finished(request, id, results) => {
+                    if !self.inference_state.is_current(request) {
+                        log::info!("Dropping results of outdated detection {request}");
+                        return Task::none();
+                    }
+                    log::info!(
+                        "Detection finished with {} boxes",
+                        results.detections.boxes.len()
+                    );
+                    let Some(index) = self.images.iter().position(|entry| entry.id == id) else {
+                        log::warn!("Dropping results for an image that is no longer open");
+                        return Task::none();
+                    };
+                    self.inference_state.record_timings(results.timings);
+                    // Boxes corrected by hand stay shown over the new results until discarded
+                    if let Some(annotations) = self.inference_state.edits.get_mut(&id) {
+                        log::info!("Keeping the edited boxes of the image over its new results");
+                        annotations.detach();
+                    }
+                    let shown = self.current_image == Some(index);
+                    let entry = &mut self.images[index];
+                    entry.results = Some(inference::ImageResults::new(&results, shown));
+                    let path = entry.path.clone();
+
+                    let mut tasks = vec![];
+                    // The annotated image of an image in the background is only auto-saved
+                    if shown {
+                        self.inference_state
+                            .set_results(inference::ImageResults::new(&results, true));
+                        self.inference_state.image.set_annotated(None);
+                        tasks.push(Task::perform(
+                            inference::DisplayImage::load(results.annotated.clone()),
+                            move |display| Message::AnnotatedDisplayed(id, display),
+                        ));
+                    }
+                    if let Some(folder) = &self.inference_state.autosave_dir {
+                        tasks.push(Task::perform(
+                            io::autosave_image(results.annotated, Some(path), folder.clone()),
+                            Message::AnnotatedSaved,
+                        ));
+                    }
+                    return Task::batch(tasks);
+                }
+                backend::Output::ModelLoading { model } => {
+                    log::info!("Loading {model}");
+                    self.inference_state.loading_model = Some((model, Instant::now()));
+                }
+                backend::Output::ModelLoaded { model, load_ms } => {
+                    log::info!("Loaded {model} in {load_ms} ms");
+                    self.inference_state.loading_model = None;
+                    self.inference_state.load_ms = Some(load_ms);
+                }
+                backend::Output::ModelLoadFailed { model } => {
+                    log::error!("Failed to load {model}");
+                    self.inference_state.loading_model = None;
+                }
+                backend::Output::ActiveDevice(device) => {
+                    log::info!("Model is running on {device}");
+                    self.inference_state.active_device = Some(device);
+                }
+                backend::Output::Failed(request, _, error) => {
+                    if self.inference_state.is_current(request) {
+                        log::error!("Detection {request} failed: {error}");
+                        self.inference_state.error = Some(error);
+                    }
+                }
+                backend::Output::Error(error) => {
+                    log::error!("Backend error: {error}");
+                    self.inference_state.loading_model = None;
+                    self.inference_state.error = Some(error);
+                }
+                backend::Output::Stopped => {
+                    log::error!("Backend stopped");
+                    self.restart_backend();
                 }
-                _ => todo!("Handle other backend outputs"),
             },
-            Message::Detect(image) => {
+            Message::Detect => {
                 log::debug!("Button pressed!");
-                self.send_to_backend(Input::ProcessImage(image));
+                self.send_edited_params();
+                if let Some(entry) = self.current_image() {
+                    let request = inference::Request::new(false);
+                    self.send_to_backend(Input::ProcessImage(request.id, entry.image_ref()));
+                    self.inference_state.request = Some(request);
+                    return Task::done(Message::DetectionStarted);
+                }
+            }
+            Message::DetectAll => {
+                self.send_edited_params();
+                let request = inference::Request::new(true);
+                let images = self.images.iter().map(|entry| entry.image_ref()).collect();
+                self.send_to_backend(Input::ProcessBatch(request.id, images));
+                self.inference_state.request = Some(request);
                 return Task::done(Message::DetectionStarted);
             }
+            Message::CancelDetection => {
+                self.cancel_detection();
+            }
             Message::LoadImage => {
                 log::debug!("Load Image button pressed!");
 
-                // Open file dialog to load an image
-                self.inference_state.selecting_image = true;
-
-                return Task::perform(io::open_image(), Message::ImageLoaded);
+                // Open file dialog to load images
+                return self.load_images(io::open_images());
+            }
+            Message::LoadFolder => {
+                return self.load_images(io::open_folder());
             }
-            Message::ImageLoaded(result) => {
+            Message::ImagesLoaded(result) => {
                 self.inference_state.selecting_image = false;
 
-                if let Ok(image) = result {
-                    log::info!("Image loaded successfully!");
+                if let Ok(images) = result {
+                    log::info!("Loaded {} images", images.len());
+                    // The results would be for images that are gone
+                    self.cancel_detection();
 
-                    // TODO: make InferenceState::set_image or something instead
-                    // let image_handle = iced::widget::image::Handle::from_bytes(DEFAULT_IMAGE.to_vec());
-                    // self.inference_state.image = inference::Image::new(Some(image_handle));
+                    self.images = images;
+                    self.current_image = None;
+                    self.inference_state.edits.clear();
 
-                    // Convert DynamicImage to iced image
-                    // let rgba = image.to_rgba8();
-                    // let handle = iced::advanced::image::Handle::from_rgba(
-                    //     rgba.width(),
-                    //     rgba.height(),
-                    //     rgba.as_raw().to_vec(),
-                    // );
-                    self.inference_state.image = inference::Image::new(&image);
+                    let paths = self
+                        .images
+                        .iter()
+                        .map(|entry| (entry.id, entry.path.clone()))
+                        .collect();
+                    let (thumbnails, handle) =
+                        Task::run(inference::thumbnails(paths), |(id, thumbnail)| {
+                            Message::ThumbnailLoaded(id, thumbnail)
+                        })
+                        .abortable();
+                    // Dropping the handle of the previous images stops their thumbnails
+                    self.thumbnail_task = Some(handle.abort_on_drop());
+                    self.thumbnails_left = self.images.len();
 
-                    self.image = Some(Arc::new(image));
+                    let decode = self.select_image(0);
+                    return Task::batch([decode, thumbnails, self.start_pending_run()]);
                 } else {
-                    log::error!("Failed to load image: {:?}", result);
+                    log::error!("Failed to load images: {:?}", result);
+                }
+            }
+            Message::ThumbnailLoaded(id, thumbnail) => {
+                if let Some(entry) = self.images.iter_mut().find(|entry| entry.id == id) {
+                    entry.thumbnail = thumbnail;
+                    self.thumbnails_left = self.thumbnails_left.saturating_sub(1);
+                }
+            }
+            Message::ImageDecoded(id, result) => {
+                let Some(index) = self.images.iter().position(|entry| entry.id == id) else {
+                    return Task::none();
+                };
+                // Moved on to another image meanwhile
+                if self.current_image != Some(index) {
+                    return Task::none();
+                }
+                match result {
+                    Ok(decoded) => {
+                        self.inference_state
+                            .image
+                            .set_image(decoded.display.clone());
+                        self.images[index].decoded = Some(decoded);
+                    }
+                    Err(error) => {
+                        log::error!(
+                            "Failed to load {}: {error:?}",
+                            self.images[index].path.display()
+                        );
+                    }
+                }
+            }
+            Message::SelectImage(index) => {
+                return self.select_image(index);
+            }
+            Message::NextImage => {
+                if let Some(index) = self.current_image {
+                    return self.select_image(index + 1);
+                }
+            }
+            Message::PreviousImage => {
+                if let Some(index) = self.current_image {
+                    return self.select_image(index.saturating_sub(1));
+                }
+            }
+            Message::SaveDetections => {
+                let Some(entry) = self.current_image() else {
+                    return Task::none();
+                };
+                if let Some((width, height)) = entry.size() {
+                    return Task::perform(
+                        io::save_detections(
+                            entry.path.clone(),
+                            width,
+                            height,
+                            self.inference_state.detections(),
+                            self.inference_state.export_class_names(),
+                        ),
+                        Message::DetectionsSaved,
+                    );
+                }
+            }
+            Message::SaveAllDetections => {
+                let images = self
+                    .images
+                    .iter()
+                    .filter_map(|entry| {
+                        let results = entry.results.as_ref()?;
+                        let detections = self.inference_state.image_detections(entry)?;
+                        Some((
+                            entry.path.clone(),
+                            results.width,
+                            results.height,
+                            detections,
+                        ))
+                    })
+                    .collect();
+                return Task::perform(
+                    io::save_all_detections(images, self.inference_state.export_class_names()),
+                    Message::DetectionsSaved,
+                );
+            }
+            Message::DetectionsSaved(result) => match result {
+                Ok(path) => log::info!("Saved detections to {}", path.display()),
+                Err(error) => log::error!("Failed to save detections: {:?}", error),
+            },
+            Message::SaveAnnotated => {
+                if let Some(annotated) = self
+                    .inference_state
+                    .results
+                    .as_ref()
+                    .and_then(|results| results.annotated.clone())
+                {
+                    return Task::perform(
+                        io::save_image_as(
+                            annotated,
+                            self.current_image().map(|entry| entry.path.clone()),
+                        ),
+                        Message::AnnotatedSaved,
+                    );
+                }
+            }
+            Message::AnnotatedSaved(result) => match result {
+                Ok(path) => log::info!("Saved annotated image to {}", path.display()),
+                Err(error) => log::error!("Failed to save annotated image: {:?}", error),
+            },
+            Message::ToggleAutosave(enabled) => {
+                if enabled {
+                    return Task::perform(io::pick_folder(), Message::AutosaveFolderSelected);
+                }
+                self.inference_state.autosave_dir = None;
+            }
+            Message::AutosaveFolderSelected(result) => {
+                if let Ok(folder) = result {
+                    log::info!("Auto-saving results to {}", folder.display());
+                    self.inference_state.autosave_dir = Some(folder);
                 }
             }
+            Message::DismissError => {
+                self.inference_state.error = None;
+            }
+            Message::DismissNotice => {
+                self.inference_state.notice = None;
+            }
             Message::GoToScreen(screen) => {
                 log::info!("Switching to screen: {:?}", screen);
                 self.screen = screen;
             }
             Message::DetectionStarted => {
                 self.inference_state.busy = true;
+                self.inference_state.progress = None;
             }
             Message::DetectionFinished => {
                 self.inference_state.busy = false;
+                self.inference_state.progress = None;
+                self.inference_state.request = None;
             }
             Message::SelectModel(model) => {
+                self.cancel_detection();
+                // So the model is built with them
+                self.send_edited_params();
                 self.inference_state.selected_model = Some(model.clone());
                 log::info!("Selected model: {:?}", model);
                 self.send_to_backend(Input::SelectModel(model));
             }
+            Message::SelectDevice(device) => {
+                self.inference_state.params.device = device;
+                self.send_params();
+            }
+            Message::ToggleLazyLoading(lazy) => {
+                self.inference_state.lazy_loading = lazy;
+                self.send_to_backend(Input::SetLazyLoading(lazy));
+            }
+            Message::AnimationTick => {}
+            Message::ToggleHud(show) => {
+                self.inference_state.show_hud = show;
+            }
+            Message::ToggleAnnotated(show) => {
+                self.inference_state.show_annotated = show;
+                self.inference_state.image.set_show_annotated(show);
+            }
+            Message::AnnotatedDisplayed(id, display) => {
+                // Dropped if another image is shown by now
+                if self.inference_state.image_id == Some(id) {
+                    self.inference_state.image.set_annotated(Some(display));
+                }
+            }
+            Message::Zoom(zoom) => {
+                self.inference_state.image.set_zoom(zoom);
+            }
+            Message::DetectionClicked(index) => {
+                self.inference_state.image.set_selected(index);
+                if let Some(index) = index {
+                    return inference::scroll_to_detection(&self.inference_state, index);
+                }
+            }
+            Message::SelectDetection(index) => {
+                self.inference_state.image.set_selected(Some(index));
+            }
+            Message::SortDetections(key) => {
+                let sort = &mut self.inference_state.sort;
+                if sort.key == key {
+                    sort.descending = !sort.descending;
+                } else {
+                    sort.key = key;
+                    // Text reads best ascending, numbers biggest first
+                    sort.descending = key != inference::SortKey::Class;
+                }
+            }
+            Message::ToggleClass(class, shown) => {
+                let mut filter = self.inference_state.class_filter.clone();
+                if shown {
+                    filter.hidden.remove(&class);
+                } else {
+                    filter.hidden.insert(class);
+                }
+                self.inference_state.set_class_filter(filter);
+            }
+            Message::ShowOnlyClass(class) => {
+                let filter = inference::ClassFilter {
+                    only: class,
+                    ..self.inference_state.class_filter.clone()
+                };
+                self.inference_state.set_class_filter(filter);
+            }
+            Message::ToggleEditing(editing) => {
+                self.inference_state.editing = editing;
+                self.inference_state.image.set_editing(editing);
+            }
+            Message::BoxDrawn(rect) => {
+                // New boxes get the first class, to be relabeled from the sidebar
+                let class = self
+                    .inference_state
+                    .export_class_names()
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| "object".to_string());
+                self.inference_state.edit(BoxEdit::Add(BoundingBox {
+                    class,
+                    confidence: 1.0,
+                    x: rect.x,
+                    y: rect.y,
+                    width: rect.width,
+                    height: rect.height,
+                }));
+            }
+            Message::BoxChanged(index, rect) => {
+                self.inference_state.edit(BoxEdit::Reshape(index, rect));
+            }
+            Message::RelabelSelected(class) => {
+                if let Some(index) = self.inference_state.image.selected() {
+                    self.inference_state.edit(BoxEdit::Relabel(index, class));
+                }
+            }
+            Message::DeleteSelected => {
+                let selected = self.inference_state.image.selected();
+                if let (true, Some(index)) = (self.inference_state.editing, selected) {
+                    self.inference_state.edit(BoxEdit::Delete(index));
+                }
+            }
+            Message::Undo => self.inference_state.undo(),
+            Message::Redo => self.inference_state.redo(),
+            Message::DiscardEdits => self.inference_state.discard_edits(),
+            Message::SelectDType(dtype) => {
+                let Some(model) = self.inference_state.selected_model.clone() else {
+                    return Task::none();
+                };
+                if let Some(config) = self.registry.get(&model) {
+                    let config = ModelConfig {
+                        dtype,
+                        ..config.clone()
+                    };
+                    self.registry.set(model.clone(), config.clone());
+                    self.send_to_backend(Input::SetModelConfig(model, config));
+                }
+            }
+            Message::BrowseWeights => {
+                return Task::perform(io::pick_weights(), Message::WeightsSelected);
+            }
+            Message::WeightsSelected(result) => {
+                if let (Ok(path), Some(model)) =
+                    (result, self.inference_state.selected_model.clone())
+                {
+                    let config = match self.registry.get(&model) {
+                        Some(config) => ModelConfig {
+                            model_file: path,
+                            ..config.clone()
+                        },
+                        None => ModelConfig::new(path),
+                    };
+                    self.registry.set(model.clone(), config.clone());
+                    self.refresh_models();
+                    self.send_to_backend(Input::SetModelConfig(model, config));
+                }
+            }
+            Message::SetClassConfidence(confidence) => {
+                self.inference_state.params.class_confidence = confidence;
+                self.inference_state.refilter();
+            }
+            Message::SetTextConfidence(confidence) => {
+                self.inference_state.params.text_confidence = confidence;
+            }
+            Message::SetNms(nms) => {
+                self.inference_state.params.post_processing.nms = nms;
+                self.send_params();
+            }
+            Message::SetIouThreshold(iou) => {
+                self.inference_state.params.post_processing.iou_threshold = iou;
+            }
+            // Typed fields are sent on Enter, like the prompts
+            Message::SetMinArea(input) => {
+                if let Ok(area) = parse_optional(&input) {
+                    self.inference_state.params.post_processing.min_area = area;
+                    self.inference_state.params_edited = true;
+                }
+                self.inference_state.post_processing_form.min_area = input;
+            }
+            Message::SetMaxArea(input) => {
+                if let Ok(area) = parse_optional(&input) {
+                    self.inference_state.params.post_processing.max_area = area;
+                    self.inference_state.params_edited = true;
+                }
+                self.inference_state.post_processing_form.max_area = input;
+            }
+            Message::SetTopK(input) => {
+                if let Ok(top_k) = parse_optional(&input) {
+                    self.inference_state.params.post_processing.top_k = top_k;
+                    self.inference_state.params_edited = true;
+                }
+                self.inference_state.post_processing_form.top_k = input;
+            }
+            Message::SetSynonyms(input) => {
+                self.inference_state.params.post_processing.synonyms =
+                    postprocess::parse_synonyms(&input);
+                self.inference_state.post_processing_form.synonyms = input;
+                self.inference_state.params_edited = true;
+            }
+            Message::ThresholdsReleased => {
+                // Only forward on release, since changing the text threshold rebuilds the model
+                self.send_params();
+            }
+            Message::AddPrompt => {
+                self.inference_state.params.class_names.push(String::new());
+            }
+            Message::EditPrompt(index, prompt) => {
+                // Sending on every keystroke would cancel detections and drop the model each time
+                if let Some(name) = self.inference_state.params.class_names.get_mut(index) {
+                    *name = prompt;
+                    self.inference_state.params_edited = true;
+                }
+            }
+            Message::SubmitParams => self.send_edited_params(),
+            Message::RemovePrompt(index) => {
+                if index < self.inference_state.params.class_names.len() {
+                    self.inference_state.params.class_names.remove(index);
+                    self.send_params();
+                }
+            }
+            Message::MovePromptUp(index) => {
+                if index > 0 && index < self.inference_state.params.class_names.len() {
+                    self.inference_state
+                        .params
+                        .class_names
+                        .swap(index - 1, index);
+                    self.send_params();
+                }
+            }
+            Message::MovePromptDown(index) => {
+                if index + 1 < self.inference_state.params.class_names.len() {
+                    self.inference_state
+                        .params
+                        .class_names
+                        .swap(index, index + 1);
+                    self.send_params();
+                }
+            }
         }
         Task::none()
     }
@@ -170,13 +942,39 @@ impl ZeroShotRust {
         }
     }
 
-    // fn backend_subscription(model: Option<backend::Models>) -> iced::Subscription<backend::Output> {
-    //     Subscription::run_with_id("backend", backend::connect(model))
-    // }
+    /// The backend of the current generation, a new generation replaces the stopped one
+    fn backend_subscription(&self) -> iced::Subscription<Message> {
+        let generation = self.backend_generation;
+        Subscription::run_with_id(("backend", generation), backend::connect())
+            .with(generation)
+            .map(|(generation, output)| Message::Backend(generation, output))
+    }
 
     pub fn subscription(&self) -> iced::Subscription<Message> {
-        let backend = Subscription::run(backend::connect).map(Message::Backend);
+        let backend = self.backend_subscription();
+        let shortcuts = keyboard::on_key_press(|key, modifiers| match key.as_ref() {
+            keyboard::Key::Named(keyboard::key::Named::ArrowLeft) => Some(Message::PreviousImage),
+            keyboard::Key::Named(keyboard::key::Named::ArrowRight) => Some(Message::NextImage),
+            keyboard::Key::Named(keyboard::key::Named::Delete) => Some(Message::DeleteSelected),
+            keyboard::Key::Character("z") | keyboard::Key::Character("Z")
+                if modifiers.command() =>
+            {
+                Some(if modifiers.shift() {
+                    Message::Redo
+                } else {
+                    Message::Undo
+                })
+            }
+            keyboard::Key::Character("y") if modifiers.command() => Some(Message::Redo),
+            _ => None,
+        });
+
+        let spinner = if self.inference_state.loading_model.is_some() {
+            iced::time::every(Duration::from_millis(30)).map(|_| Message::AnimationTick)
+        } else {
+            Subscription::none()
+        };
 
-        Subscription::batch([backend])
+        Subscription::batch([backend, shortcuts, spinner])
     }
 }
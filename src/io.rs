@@ -1,24 +1,279 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use anyhow::Context;
 use rfd::AsyncFileDialog;
 
+use crate::export::{self, ExportFormat, ImageDetections};
+use crate::model::Detections;
+
+/// File extensions (lowercase) we know how to load
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// Formats offered when saving images, the format is picked from the extension
+const SAVE_FILTERS: &[(&str, &[&str])] = &[
+    ("PNG", &["png"]),
+    ("JPEG", &["jpg", "jpeg"]),
+    ("WebP", &["webp"]),
+];
+
 #[derive(Debug, Clone)]
 pub enum LoadError {
     Cancelled,
     FileError,
 }
 
+#[derive(Debug, Clone)]
+pub enum SaveError {
+    Cancelled,
+    FileError,
+}
+
 pub fn load_image(path: &Path) -> Result<image::DynamicImage, LoadError> {
     let image = image::open(path).or(Err(LoadError::FileError))?;
     Ok(image.into())
 }
 
-pub async fn open_image() -> Result<image::DynamicImage, LoadError> {
+/// Ask for image files to open. Only their paths are returned, images are decoded when needed.
+pub async fn open_images() -> Result<Vec<PathBuf>, LoadError> {
+    let files = AsyncFileDialog::new()
+        .add_filter("Image Files", IMAGE_EXTENSIONS)
+        .pick_files()
+        .await
+        .ok_or(LoadError::Cancelled)?;
+
+    Ok(files.iter().map(|file| file.path().to_path_buf()).collect())
+}
+
+/// The images given as files or directories, e.g. on the command line
+pub async fn open_paths(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, LoadError> {
+    let paths = collect_images(&paths).or(Err(LoadError::FileError))?;
+    if paths.is_empty() {
+        return Err(LoadError::FileError);
+    }
+    Ok(paths)
+}
+
+/// Ask for a folder and list the images in it
+pub async fn open_folder() -> Result<Vec<PathBuf>, LoadError> {
+    let folder = pick_folder().await?;
+    open_paths(vec![folder]).await
+}
+
+/// Ask where to save detections, returning the path and the format picked by its extension
+async fn pick_export_file(stem: &str) -> Result<(PathBuf, ExportFormat), SaveError> {
+    let dialog = ExportFormat::ALL
+        .iter()
+        .fold(AsyncFileDialog::new(), |dialog, format| {
+            dialog.add_filter(format.to_string(), &[format.extension()])
+        })
+        .set_file_name(format!("{stem}.{}", ExportFormat::Coco.extension()));
+    let file = dialog.save_file().await.ok_or(SaveError::Cancelled)?;
+    let path = file.path().to_path_buf();
+
+    let format = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ExportFormat::from_extension)
+        .unwrap_or(ExportFormat::Coco);
+    Ok((path, format))
+}
+
+/// Ask where to save the detections for an image, picking the format from the file extension
+pub async fn save_detections(
+    image_path: PathBuf,
+    width: u32,
+    height: u32,
+    detections: Detections,
+    class_names: Vec<String>,
+) -> Result<PathBuf, SaveError> {
+    let stem = image_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "detections".to_string());
+    let (path, format) = pick_export_file(&stem).await?;
+    let label_path = path.clone();
+    blocking(move || {
+        let image = ImageDetections {
+            path: &image_path,
+            width,
+            height,
+            detections: &detections,
+        };
+        export::write(&label_path, format, &image, &class_names).map_err(|error| {
+            log::error!("Failed to save detections: {error:#}");
+            SaveError::FileError
+        })
+    })
+    .await?;
+
+    Ok(path)
+}
+
+/// Ask where to save the detections for all the given images, as `(path, width, height,
+/// detections)`. COCO goes to the chosen file, YOLO and VOC to one file per image named after
+/// the image, in the folder of the chosen file.
+pub async fn save_all_detections(
+    images: Vec<(PathBuf, u32, u32, Detections)>,
+    class_names: Vec<String>,
+) -> Result<PathBuf, SaveError> {
+    let (path, format) = pick_export_file("annotations").await?;
+    let export_path = path.clone();
+    blocking(move || {
+        write_all_detections(&export_path, format, &images, &class_names).map_err(|error| {
+            log::error!("Failed to save detections: {error:#}");
+            SaveError::FileError
+        })
+    })
+    .await?;
+
+    Ok(path)
+}
+
+fn write_all_detections(
+    path: &Path,
+    format: ExportFormat,
+    images: &[(PathBuf, u32, u32, Detections)],
+    class_names: &[String],
+) -> anyhow::Result<()> {
+    let images = images
+        .iter()
+        .map(|(path, width, height, detections)| ImageDetections {
+            path,
+            width: *width,
+            height: *height,
+            detections,
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Coco => {
+            let json = export::to_coco(&images, class_names)?;
+            std::fs::write(path, json)
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        ExportFormat::Yolo | ExportFormat::Voc => {
+            let folder = path.parent().unwrap_or(Path::new(""));
+            let paths = images
+                .iter()
+                .map(|image| image.path.to_path_buf())
+                .collect::<Vec<_>>();
+            let stems = export::file_stems(&paths)?;
+            images.iter().zip(stems).try_for_each(|(image, stem)| {
+                let label_path = folder.join(format!("{stem}.{}", format.extension()));
+                export::write(&label_path, format, image, class_names)
+            })
+        }
+    }
+}
+
+/// Encode and write files on a blocking thread, so saving doesn't stall the UI
+async fn blocking<T: Send + 'static>(
+    save: impl FnOnce() -> Result<T, SaveError> + Send + 'static,
+) -> Result<T, SaveError> {
+    tokio::task::spawn_blocking(save)
+        .await
+        .unwrap_or_else(|error| {
+            log::error!("Saving failed: {error}");
+            Err(SaveError::FileError)
+        })
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Expand a list of files and directories into image files, sorted per directory.
+/// Directories are not searched recursively.
+pub fn collect_images(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.retain(|entry| entry.is_file() && is_image(entry));
+            entries.sort();
+            images.extend(entries);
+        } else {
+            images.push(path.clone());
+        }
+    }
+    Ok(images)
+}
+
+pub async fn pick_weights() -> Result<PathBuf, LoadError> {
     let file = AsyncFileDialog::new()
-        .add_filter("Image Files", &["png", "jpg", "jpeg"])
+        .add_filter("ONNX models", &["onnx"])
         .pick_file()
         .await
         .ok_or(LoadError::Cancelled)?;
+    Ok(file.path().to_path_buf())
+}
+
+pub async fn pick_folder() -> Result<PathBuf, LoadError> {
+    let folder = AsyncFileDialog::new()
+        .pick_folder()
+        .await
+        .ok_or(LoadError::Cancelled)?;
+    Ok(folder.path().to_path_buf())
+}
+
+pub fn save_image(path: &Path, image: &image::DynamicImage) -> Result<(), SaveError> {
+    // JPEG has no alpha channel
+    let result = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if ["jpg", "jpeg"].contains(&extension.to_lowercase().as_str()) => {
+            image.to_rgb8().save(path)
+        }
+        _ => image.save(path),
+    };
+    result.map_err(|error| {
+        log::error!("Failed to save image to {}: {error}", path.display());
+        SaveError::FileError
+    })
+}
+
+/// Ask where to save an image, suggesting a file name based on the source image
+pub async fn save_image_as(
+    image: Arc<image::DynamicImage>,
+    source_path: Option<PathBuf>,
+) -> Result<PathBuf, SaveError> {
+    let stem = source_path
+        .as_deref()
+        .and_then(Path::file_stem)
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_string());
+    let dialog = SAVE_FILTERS
+        .iter()
+        .fold(AsyncFileDialog::new(), |dialog, (name, extensions)| {
+            dialog.add_filter(*name, extensions)
+        })
+        .set_file_name(format!("{stem}_annotated.png"));
+    let file = dialog.save_file().await.ok_or(SaveError::Cancelled)?;
+    let path = file.path().to_path_buf();
+
+    let image_path = path.clone();
+    blocking(move || save_image(&image_path, &image)).await?;
+    Ok(path)
+}
+
+/// Save an image into `folder` with a timestamped name based on the source image
+pub async fn autosave_image(
+    image: Arc<image::DynamicImage>,
+    source_path: Option<PathBuf>,
+    folder: PathBuf,
+) -> Result<PathBuf, SaveError> {
+    let stem = source_path
+        .as_deref()
+        .and_then(Path::file_stem)
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_string());
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+    let path = folder.join(format!("{stem}_{timestamp}.png"));
 
-    load_image(file.path())
+    let image_path = path.clone();
+    blocking(move || save_image(&image_path, &image)).await?;
+    Ok(path)
 }
@@ -0,0 +1,8 @@
+pub mod backend;
+pub mod export;
+pub mod frontend;
+pub mod io;
+pub mod logging;
+pub mod model;
+pub mod palette;
+pub mod screen;
@@ -24,6 +24,8 @@ pub fn init_logging() -> Result<()> {
         .with_thread_ids(true)
         .with_line_number(true)
         .with_file(true)
+        // Log how long each span took, such as the stages of a detection
+        .with_span_events(fmt::format::FmtSpan::CLOSE)
         .with_filter(filter);
 
     // Initialize the subscriber
@@ -1,14 +1,11 @@
-mod backend;
-mod frontend;
-mod io;
-mod logging;
-mod model;
-mod screen;
-
 // use backend::{Backend, Input, Output};
-use frontend::ZeroShotRust;
+use zeroshot_rust::backend::{self, ModelType};
+use zeroshot_rust::frontend::{Startup, ZeroShotRust};
+use zeroshot_rust::logging;
+use zeroshot_rust::model::registry::ModelRegistry;
 
 use anyhow::Context;
+use std::path::PathBuf;
 // use futures::{SinkExt, Stream, StreamExt};
 // use iced::widget::{button, center, checkbox, column, container, row, text, Space};
 // use iced::{Element, Font, Length, Subscription, Task};
@@ -20,16 +17,54 @@ use anyhow::Context;
 // use std::thread;
 // use tracing::instrument::WithSubscriber;
 
+#[derive(argh::FromArgs)]
+/// Run zero-shot computer vision models on images
+struct Args {
+    /// JSON file with the weights to use for each model
+    #[argh(option)]
+    model_config: Option<PathBuf>,
+
+    /// model to select (mock, grounding-dino, owlv2, yolo, yolo-seg, yolo-pose or rt-detr)
+    #[argh(option)]
+    model: Option<ModelType>,
+
+    /// comma separated text prompts, e.g. "person,car,bus"
+    #[argh(option)]
+    prompts: Option<String>,
+
+    /// confidence threshold for both classes and text
+    #[argh(option)]
+    conf: Option<f32>,
+
+    /// detect on all the given images on startup
+    #[argh(switch)]
+    run: bool,
+
+    /// image files or directories of images to open
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+}
+
 pub fn main() -> anyhow::Result<()> {
     logging::init_logging()?;
     log::info!("Starting the application...");
 
+    let args: Args = argh::from_env();
+    let registry = ModelRegistry::load(args.model_config.as_deref())?;
+    let startup = Startup {
+        images: args.inputs,
+        model: args.model,
+        prompts: args.prompts.as_deref().map(backend::parse_prompts),
+        confidence: args.conf,
+        run: args.run,
+    };
+
     iced::application(
         ZeroShotRust::title,
         ZeroShotRust::update,
         ZeroShotRust::view,
     )
     .subscription(ZeroShotRust::subscription)
-    .run_with(ZeroShotRust::new)
+    .run_with(move || ZeroShotRust::new(registry, startup))
     .context("Failed to run the application")
 }
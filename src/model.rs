@@ -1,5 +1,7 @@
 pub mod mock;
 pub mod onnx;
+pub mod postprocess;
+pub mod registry;
 
 use anyhow::Result;
 use futures::{
@@ -8,47 +10,448 @@ use futures::{
     stream, SinkExt, StreamExt,
 };
 use image::DynamicImage;
-use std::{default, future::Future, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::{default, future::Future, sync::Arc, time::Duration};
 // use iced::Result;
 use async_trait::async_trait;
 use usls::{Annotator, DataLoader, Options};
 
 use crate::backend::DetectionParams;
+use registry::ModelConfig;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ModelType {
+    #[serde(rename = "mock")]
     Mock,
+    #[serde(rename = "grounding-dino")]
     GroundingDINO,
+    #[serde(rename = "owlv2")]
+    OWLv2,
+    #[serde(rename = "yolo")]
+    Yolo,
+    #[serde(rename = "yolo-seg")]
+    YoloSeg,
+    #[serde(rename = "yolo-pose")]
+    YoloPose,
+    #[serde(rename = "rt-detr")]
+    RTDETR,
 }
 
-#[derive(Debug, Clone)]
+/// What a model can do, so the UI can adapt to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Detects whatever the text prompts describe, instead of a fixed set of classes
+    pub open_vocabulary: bool,
+    pub boxes: bool,
+    pub masks: bool,
+    pub keypoints: bool,
+}
+
+impl Capabilities {
+    const DETECTOR: Capabilities = Capabilities {
+        open_vocabulary: false,
+        boxes: true,
+        masks: false,
+        keypoints: false,
+    };
+}
+
+impl ModelType {
+    pub const ALL: [ModelType; 7] = [
+        ModelType::Mock,
+        ModelType::GroundingDINO,
+        ModelType::OWLv2,
+        ModelType::Yolo,
+        ModelType::YoloSeg,
+        ModelType::YoloPose,
+        ModelType::RTDETR,
+    ];
+
+    /// Whether the model loads weights from disk, and so needs an entry in the registry
+    pub fn needs_weights(&self) -> bool {
+        !matches!(self, Self::Mock)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            Self::Mock | Self::GroundingDINO | Self::OWLv2 => Capabilities {
+                open_vocabulary: true,
+                ..Capabilities::DETECTOR
+            },
+            Self::Yolo | Self::RTDETR => Capabilities::DETECTOR,
+            Self::YoloSeg => Capabilities {
+                masks: true,
+                ..Capabilities::DETECTOR
+            },
+            Self::YoloPose => Capabilities {
+                keypoints: true,
+                ..Capabilities::DETECTOR
+            },
+        }
+    }
+
+    /// The classes a closed-set model detects, or `None` if they come from the prompts.
+    ///
+    /// These are the classes of the default COCO weights, custom weights may differ.
+    pub fn class_names(&self) -> Option<&'static [&'static str]> {
+        if self.capabilities().open_vocabulary {
+            None
+        } else {
+            Some(&usls::COCO_CLASS_NAMES_80)
+        }
+    }
+}
+
+/// Execution provider to run a model on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    Cpu,
+    Cuda,
+    TensorRT,
+    OpenVINO,
+}
+
+impl Device {
+    pub const ALL: [Device; 4] = [
+        Device::Cpu,
+        Device::Cuda,
+        Device::TensorRT,
+        Device::OpenVINO,
+    ];
+
+    /// Whether support for the device was compiled in, see the cargo features
+    pub fn is_compiled(&self) -> bool {
+        match self {
+            Self::Cpu => true,
+            Self::Cuda => cfg!(feature = "cuda"),
+            Self::TensorRT => cfg!(feature = "tensorrt"),
+            Self::OpenVINO => cfg!(feature = "openvino"),
+        }
+    }
+
+    pub fn available() -> Vec<Device> {
+        Self::ALL.into_iter().filter(Device::is_compiled).collect()
+    }
+
+    /// Device name as understood by usls, always using the first device of its kind
+    pub fn usls_name(&self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu:0",
+            Self::Cuda => "cuda:0",
+            Self::TensorRT => "trt:0",
+            Self::OpenVINO => "openvino:0",
+        }
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        if cfg!(feature = "cuda") {
+            Self::Cuda
+        } else {
+            Self::Cpu
+        }
+    }
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Cpu => "CPU",
+            Self::Cuda => "CUDA",
+            Self::TensorRT => "TensorRT",
+            Self::OpenVINO => "OpenVINO",
+        })
+    }
+}
+
+impl std::str::FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cpu" => Ok(Self::Cpu),
+            "cuda" => Ok(Self::Cuda),
+            "tensorrt" | "trt" => Ok(Self::TensorRT),
+            "openvino" => Ok(Self::OpenVINO),
+            _ => Err(format!(
+                "Unknown device '{s}', expected cpu, cuda, tensorrt or openvino"
+            )),
+        }
+    }
+}
+
+/// Data type of the model weights
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DType {
+    #[default]
+    Auto,
+    Fp32,
+    Fp16,
+    Q8,
+}
+
+impl DType {
+    pub const ALL: [DType; 4] = [DType::Auto, DType::Fp32, DType::Fp16, DType::Q8];
+
+    /// Data type name as understood by usls
+    pub fn usls_name(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Fp32 => "fp32",
+            Self::Fp16 => "fp16",
+            Self::Q8 => "q8",
+        }
+    }
+}
+
+impl std::fmt::Display for DType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Fp32 => "fp32",
+            Self::Fp16 => "fp16",
+            Self::Q8 => "q8",
+        })
+    }
+}
+
+/// Axis-aligned bounding box in image pixel coordinates, with (x, y) being the top-left corner
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoundingBox {
-    class: String,
-    confidence: f32,
-    x: f32,
-    y: f32,
-    width: f32,
-    height: f32,
+    pub class: String,
+    pub confidence: f32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
 }
 
-#[derive(Debug, Clone)]
+/// Bounding box rotated to fit the object, as its four corners in image pixel coordinates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotatedBox {
+    pub class: String,
+    pub confidence: f32,
+    pub vertices: [[f32; 2]; 4],
+}
+
+/// Instance outline in image pixel coordinates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Polygon {
+    pub class: String,
+    pub confidence: f32,
+    pub points: Vec<[f32; 2]>,
+}
+
+/// Instance mask covering the whole image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mask {
+    pub class: String,
+    pub confidence: f32,
+    pub width: u32,
+    pub height: u32,
+    /// Row-major coverage from 0 to 255, left out of the JSON output where the polygons
+    /// describe the same shapes far more compactly
+    #[serde(skip)]
+    pub data: Arc<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keypoint {
+    pub x: f32,
+    pub y: f32,
+    /// Zero for keypoints the model could not place
+    pub confidence: f32,
+}
+
+/// Pairs of COCO keypoint indices to connect when drawing a pose
+pub const COCO_SKELETON: [(usize, usize); 16] = [
+    (0, 1),
+    (0, 2),
+    (1, 3),
+    (2, 4),
+    (5, 6),
+    (5, 7),
+    (7, 9),
+    (6, 8),
+    (8, 10),
+    (5, 11),
+    (6, 12),
+    (11, 12),
+    (11, 13),
+    (13, 15),
+    (12, 14),
+    (14, 16),
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Detections {
-    boxes: Vec<BoundingBox>,
+    pub boxes: Vec<BoundingBox>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rotated_boxes: Vec<RotatedBox>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub polygons: Vec<Polygon>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masks: Vec<Mask>,
+    /// One list of keypoints per instance, in [`COCO_SKELETON`] order for pose models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<Vec<Keypoint>>,
+}
+
+impl Detections {
+    /// The detections with at least the given confidence.
+    ///
+    /// Polygons, masks and keypoints that come one per box go with their box, so they stay in
+    /// line with the boxes they are drawn with. Otherwise keypoints are kept as they are.
+    pub fn filter_confidence(&self, confidence: f32) -> Detections {
+        let shown = self
+            .boxes
+            .iter()
+            .map(|bbox| bbox.confidence >= confidence)
+            .collect::<Vec<_>>();
+        let per_box = |count: usize| count == shown.len();
+
+        Detections {
+            boxes: kept(&self.boxes, &shown),
+            rotated_boxes: self
+                .rotated_boxes
+                .iter()
+                .filter(|rotated| rotated.confidence >= confidence)
+                .cloned()
+                .collect(),
+            polygons: if per_box(self.polygons.len()) {
+                kept(&self.polygons, &shown)
+            } else {
+                self.polygons
+                    .iter()
+                    .filter(|polygon| polygon.confidence >= confidence)
+                    .cloned()
+                    .collect()
+            },
+            masks: if per_box(self.masks.len()) {
+                kept(&self.masks, &shown)
+            } else {
+                self.masks
+                    .iter()
+                    .filter(|mask| mask.confidence >= confidence)
+                    .cloned()
+                    .collect()
+            },
+            keypoints: kept(&self.keypoints, &shown),
+        }
+    }
+
+    /// Number of objects of each class, going by the boxes. Classes only seen in polygons or
+    /// masks are listed with a count of zero.
+    pub fn class_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        let objects = self
+            .boxes
+            .iter()
+            .map(|bbox| &bbox.class)
+            .chain(self.rotated_boxes.iter().map(|rotated| &rotated.class));
+        for class in objects {
+            *counts.entry(class.clone()).or_insert(0) += 1;
+        }
+        let outlines = self
+            .polygons
+            .iter()
+            .map(|polygon| &polygon.class)
+            .chain(self.masks.iter().map(|mask| &mask.class));
+        for class in outlines {
+            counts.entry(class.clone()).or_insert(0);
+        }
+        counts
+    }
+}
+
+/// The items whose box is `shown`, or all of them if they don't come one per box
+fn kept<T: Clone>(items: &[T], shown: &[bool]) -> Vec<T> {
+    if items.len() != shown.len() {
+        return items.to_vec();
+    }
+    items
+        .iter()
+        .zip(shown)
+        .filter(|(_, shown)| **shown)
+        .map(|(item, _)| item.clone())
+        .collect()
+}
+
+/// Time spent in each stage of a detection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    pub preprocess: Duration,
+    pub inference: Duration,
+    pub postprocess: Duration,
+    pub annotation: Duration,
+    /// Wall time of the whole detection, including anything outside the stages
+    pub total: Duration,
+}
+
+impl Timings {
+    pub fn stages(&self) -> [(&'static str, Duration); 5] {
+        [
+            ("preprocess", self.preprocess),
+            ("inference", self.inference),
+            ("postprocess", self.postprocess),
+            ("annotation", self.annotation),
+            ("total", self.total),
+        ]
+    }
+
+    /// Average of each stage, zero if there are no timings
+    pub fn mean<'a>(timings: impl ExactSizeIterator<Item = &'a Timings>) -> Timings {
+        let count = timings.len().max(1) as u32;
+        let sum = timings.fold(Timings::default(), |sum, timings| Timings {
+            preprocess: sum.preprocess + timings.preprocess,
+            inference: sum.inference + timings.inference,
+            postprocess: sum.postprocess + timings.postprocess,
+            annotation: sum.annotation + timings.annotation,
+            total: sum.total + timings.total,
+        });
+        Timings {
+            preprocess: sum.preprocess / count,
+            inference: sum.inference / count,
+            postprocess: sum.postprocess / count,
+            annotation: sum.annotation / count,
+            total: sum.total / count,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DetectionResults {
-    detections: Detections,
+    pub detections: Detections,
     // y: usls::Y,
-    annotated: DynamicImage,
+    pub annotated: Arc<DynamicImage>,
+    pub timings: Timings,
 }
 
 pub trait DetectionModel: Send {
+    /// Detect objects in the image, cleaned up by the post-processing in the parameters before
+    /// the annotated image is drawn
     fn detect(&mut self, image: &DynamicImage) -> Result<DetectionResults>;
-    fn new(parameters: &DetectionParams) -> Self
+    /// Create the model, `config` is `None` for models that don't need any files
+    fn new(config: Option<&ModelConfig>, parameters: &DetectionParams) -> Self
     where
         Self: Sized;
-    // fn update_params(&mut self, params: &DetectionParams) -> Result<()>;
+    fn update_params(&mut self, params: &DetectionParams) -> Result<()>;
+    /// Load the weights now instead of on the first detection
+    fn load(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Whether the model can detect without loading anything first
+    fn is_loaded(&self) -> bool {
+        true
+    }
+    /// The execution provider the model actually runs on, once loaded
+    fn device(&self) -> Option<Device> {
+        None
+    }
     // fn clone_box(&self) -> Box<dyn DetectionModel>;
     // fn model_type(&self) -> ModelType;
 }
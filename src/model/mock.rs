@@ -1,17 +1,27 @@
-use super::{DetectionModel, DetectionResults, Detections, ModelType};
+use super::registry::ModelConfig;
+use super::{DetectionModel, DetectionResults, Detections, Device, ModelType, Timings};
 use crate::backend::DetectionParams;
 
 use anyhow::Result;
 use image::DynamicImage;
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct MockModel {}
 
 impl DetectionModel for MockModel {
-    fn new(_parameters: &DetectionParams) -> Self {
+    fn new(_config: Option<&ModelConfig>, _parameters: &DetectionParams) -> Self {
         MockModel::default()
     }
 
+    fn update_params(&mut self, _params: &DetectionParams) -> Result<()> {
+        Ok(())
+    }
+
+    fn device(&self) -> Option<Device> {
+        Some(Device::Cpu)
+    }
+
     fn detect(&mut self, image_data: &DynamicImage) -> Result<DetectionResults> {
         // async move {
         // let mut sender = sender.clone();
@@ -27,8 +37,9 @@ impl DetectionModel for MockModel {
 
         // Return mock results
         let results = DetectionResults {
-            detections: Detections { boxes: vec![] },
-            annotated: image_data.clone(),
+            detections: Detections::default(),
+            annotated: Arc::new(image_data.clone()),
+            timings: Timings::default(),
         };
 
         Ok(results)
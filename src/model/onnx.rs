@@ -1,85 +1,381 @@
-use anyhow::Result;
+//! Models run through usls on ONNX Runtime.
+//!
+//! [`ONNXModel`] takes care of the weights, execution provider and parameters, and each model
+//! family in the submodules implements [`UslsModel`] for the parts that differ.
+
+pub mod grounding_dino;
+pub mod owlv2;
+pub mod rtdetr;
+pub mod yolo;
+
+use anyhow::{Context, Result};
 use image::DynamicImage;
-use usls::{Annotator, DataLoader, GroundingDINO, Options};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use usls::{Annotator, Options, Xs};
+
+use super::postprocess::PostProcessing;
+use super::registry::{ModelConfig, ModelRegistry};
+use super::{
+    kept, BoundingBox, DetectionModel, DetectionResults, Detections, Device, Keypoint, Mask,
+    ModelType, Polygon, RotatedBox, Timings,
+};
+use crate::backend::{BackendError, DetectionParams};
+use crate::palette;
 
-use super::{BoundingBox, DetectionModel, DetectionResults, Detections, ModelType};
-use crate::backend::DetectionParams;
+/// A usls model family that can be run by [`ONNXModel`]
+pub trait UslsModel: Sized + Send {
+    const MODEL_TYPE: ModelType;
+
+    /// Family specific options, the files, dtype and device are added by [`ONNXModel`]
+    fn options(params: &DetectionParams) -> Options;
+    fn build(options: Options) -> Result<Self>;
+    // The stages of usls' `forward`, separately so they can be timed
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs>;
+    fn inference(&mut self, xs: Xs) -> Result<Xs>;
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>>;
+    /// Name used by the usls annotator
+    fn spec(&self) -> &str;
+}
 
-pub struct ONNXModel {
+pub struct ONNXModel<M: UslsModel> {
+    config: Option<ModelConfig>,
     params: DetectionParams,
-    model: Option<usls::models::GroundingDINO>,
+    model: Option<M>,
+    /// Where the model ended up running, which may differ from the requested device
+    device: Option<Device>,
 }
 
-impl Default for ONNXModel {
+impl<M: UslsModel> Default for ONNXModel<M> {
     fn default() -> Self {
-        ONNXModel::new(&DetectionParams::default())
+        ONNXModel::new(
+            ModelRegistry::default().get(&M::MODEL_TYPE),
+            &DetectionParams::default(),
+        )
     }
 }
 
-impl ONNXModel {
-    fn get_model(&mut self) -> &mut usls::models::GroundingDINO {
-        self.model.get_or_insert_with(|| {
-            let class_names = self.params.class_names.clone();
-            let options = Options::grounding_dino()
-                .with_model_file(concat!(
-                    env!("CARGO_MANIFEST_DIR"),
-                    "/weights/grounding-dino/swint-ogc.onnx"
-                ))
-                .with_text_names(&class_names.iter().map(|x| x.as_str()).collect::<Vec<_>>())
-                .with_class_confs(&[self.params.confidence_threshold])
-                .with_text_confs(&[self.params.confidence_threshold])
-                .commit()
-                .expect("Failed to create options");
-
-            log::info!("Creating model with options: {:?}", options);
-            let model = GroundingDINO::new(options).expect("Failed to create model");
-            log::info!("Model initialized");
-
-            model
-        })
+impl<M: UslsModel> ONNXModel<M> {
+    fn build(&self, config: &ModelConfig, device: Device) -> Result<M> {
+        let dtype = config
+            .dtype
+            .usls_name()
+            .try_into()
+            .map_err(|error| BackendError::BadParams(format!("{error:#}")))?;
+        let usls_device = device
+            .usls_name()
+            .try_into()
+            .map_err(|error| BackendError::OrtInit(format!("{error:#}")))?;
+        let mut options = M::options(&self.params)
+            .with_model_file(&config.model_file.to_string_lossy())
+            .with_model_dtype(dtype)
+            .with_model_device(usls_device);
+        if let Some(file) = &config.tokenizer_file {
+            options = options.with_tokenizer_file(&file.to_string_lossy());
+        }
+        if let Some(file) = &config.config_file {
+            options = options.with_config_file(&file.to_string_lossy());
+        }
+        let options = options
+            .commit()
+            .map_err(|error| BackendError::BadParams(format!("{error:#}")))?;
+
+        log::info!(
+            "Creating {} on {device} with options: {:?}",
+            M::MODEL_TYPE,
+            options
+        );
+        let model =
+            M::build(options).map_err(|error| BackendError::OrtInit(format!("{error:#}")))?;
+        Ok(model)
+    }
+
+    fn get_model(&mut self) -> Result<&mut M> {
+        if self.model.is_none() {
+            let config = self
+                .config
+                .as_ref()
+                .ok_or(BackendError::ModelNotConfigured(M::MODEL_TYPE))?;
+            config.validate()?;
+            if M::MODEL_TYPE.capabilities().open_vocabulary && self.params.class_names.is_empty() {
+                return Err(BackendError::BadParams("No prompts given".to_string()).into());
+            }
+
+            let mut device = self.params.device;
+            if !device.is_compiled() {
+                log::warn!("Built without {device} support, falling back to CPU");
+                device = Device::Cpu;
+            }
+            // The provider libraries may be missing at runtime even when support is compiled in
+            let model = match self.build(config, device) {
+                Ok(model) => model,
+                Err(error) if device != Device::Cpu => {
+                    log::warn!("Failed to initialize {device}, falling back to CPU: {error:#}");
+                    device = Device::Cpu;
+                    self.build(config, device)?
+                }
+                Err(error) => return Err(error),
+            };
+            log::info!("Model initialized on {device}");
+
+            self.model = Some(model);
+            self.device = Some(device);
+        }
+        Ok(self.model.as_mut().expect("Model was just initialized"))
     }
 }
 
-impl DetectionModel for ONNXModel {
-    fn new(parameters: &DetectionParams) -> Self {
+impl<M: UslsModel> DetectionModel for ONNXModel<M> {
+    fn new(config: Option<&ModelConfig>, parameters: &DetectionParams) -> Self {
         ONNXModel {
+            config: config.cloned(),
             model: None,
+            device: None,
             params: parameters.clone(),
         }
     }
 
+    fn update_params(&mut self, params: &DetectionParams) -> Result<()> {
+        // usls bakes the prompts and thresholds into the model at construction and has no way
+        // to swap them afterwards, so drop the model and let the next detection rebuild it
+        if params.model_changed(&self.params) && self.model.take().is_some() {
+            log::info!("Parameters changed, model will be rebuilt on next detection");
+        }
+        self.params = params.clone();
+        Ok(())
+    }
+
+    fn device(&self) -> Option<Device> {
+        self.device
+    }
+
+    fn load(&mut self) -> Result<()> {
+        self.get_model().map(|_| ())
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.model.is_some()
+    }
+
     fn detect(&mut self, image: &DynamicImage) -> Result<DetectionResults> {
-        let model = self.get_model();
+        let _span = tracing::info_span!("detect", model = %M::MODEL_TYPE).entered();
+        let confidence = self.params.class_confidence;
+        let post_processing = self.params.post_processing.clone();
+        let model = self.get_model()?;
         let xs = vec![image.clone()];
-        let ys = model.forward(&xs)?;
 
-        let annotator = Annotator::default()
-            .with_bboxes_thickness(4)
-            .with_saveout(model.spec());
-        let annotated = annotator.plot(&xs, &ys, false)?;
+        let mut timings = Timings::default();
+        let inputs = timed("preprocess", &mut timings.preprocess, || {
+            model.preprocess(&xs)
+        })?;
+        let outputs = timed("inference", &mut timings.inference, || {
+            model.inference(inputs)
+        })?;
+        let ys = timed("postprocess", &mut timings.postprocess, || {
+            model.postprocess(outputs)
+        })?;
+        let y = ys.first().context("Model returned no results")?;
+        // Before annotating, so the annotated image shows what is left of the detections
+        let (detections, kept_boxes) = post_processing.apply_with_indices(y.clone().into());
+
+        let annotated = timed("annotation", &mut timings.annotation, || {
+            let ys = [for_annotation(y, &kept_boxes, &post_processing, confidence)];
+            Annotator::default()
+                .with_bboxes_thickness(4)
+                .with_saveout(model.spec())
+                .plot(&xs, &ys, false)
+        })?;
 
         Ok(DetectionResults {
             // y: ys[0].clone(),
-            annotated: annotated.first().expect("No annotated image found").clone(),
-            detections: ys[0].clone().into(),
+            annotated: Arc::new(
+                annotated
+                    .into_iter()
+                    .next()
+                    .context("No annotated image found")?,
+            ),
+            detections,
+            timings,
         })
     }
 }
 
+/// What the usls annotator draws of a result: the objects kept by the post-processing and
+/// passing the class threshold, since the model is built with a lower one. Classes are merged
+/// as by the post-processing, and each object gets the palette index of its class as id. The
+/// annotator colors by id, so this makes saved images use the same colors as the GUI.
+fn for_annotation(
+    y: &usls::Y,
+    kept_boxes: &[usize],
+    post_processing: &PostProcessing,
+    confidence: f32,
+) -> usls::Y {
+    let label = |name: Option<&str>| {
+        let class = post_processing.merged_class(name.unwrap_or("unknown"));
+        (palette::class_index(class) as isize, class.to_string())
+    };
+    let mut y = y.clone();
+    let bboxes = y.bboxes().unwrap_or_default();
+    let mut shown = vec![false; bboxes.len()];
+    for &index in kept_boxes {
+        shown[index] = bboxes[index].confidence() >= confidence;
+    }
+
+    if !bboxes.is_empty() {
+        let bboxes = kept(bboxes, &shown)
+            .into_iter()
+            .map(|bbox| {
+                let (id, class) = label(bbox.name());
+                bbox.with_id(id).with_name(&class)
+            })
+            .collect::<Vec<_>>();
+        // Masks and keypoints have no score, but come one per box when there are boxes
+        let masks = y.masks().map(|masks| kept(masks, &shown));
+        let keypoints = y.keypoints().map(|keypoints| kept(keypoints, &shown));
+        y = y.with_bboxes(&bboxes);
+        if let Some(masks) = masks {
+            y = y.with_masks(&masks);
+        }
+        if let Some(keypoints) = keypoints {
+            y = y.with_keypoints(&keypoints);
+        }
+    }
+    if let Some(mbrs) = y.mbrs() {
+        let mbrs = mbrs
+            .iter()
+            .filter(|mbr| mbr.confidence() >= confidence)
+            .map(|mbr| {
+                let (id, class) = label(mbr.name());
+                mbr.clone().with_id(id).with_name(&class)
+            })
+            .collect::<Vec<_>>();
+        y = y.with_mbrs(&mbrs);
+    }
+    if let Some(polygons) = y.polygons() {
+        // Like masks, polygons go with their box when there is one per box
+        let polygons = if polygons.len() == shown.len() {
+            kept(polygons, &shown)
+        } else {
+            polygons
+                .iter()
+                .filter(|polygon| polygon.confidence() >= confidence)
+                .cloned()
+                .collect()
+        };
+        let polygons = polygons
+            .into_iter()
+            .map(|polygon| {
+                let (id, class) = label(polygon.name());
+                polygon.with_id(id).with_name(&class)
+            })
+            .collect::<Vec<_>>();
+        y = y.with_polygons(&polygons);
+    }
+    y
+}
+
+/// Run one stage of a detection in its own span, storing how long it took
+fn timed<T>(stage: &'static str, duration: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let _span = tracing::info_span!("stage", stage).entered();
+    let start = Instant::now();
+    let result = f();
+    *duration = start.elapsed();
+    result
+}
+
 // usls::Y into DetectionResults
 impl From<usls::Y> for Detections {
     fn from(y: usls::Y) -> Self {
-        let mut boxes = Vec::new();
-        for bbox in y.bboxes().expect("Failed to get bounding boxes") {
-            boxes.push(BoundingBox {
+        // Kinds the model doesn't output are reported as None
+        let bboxes = y.bboxes().unwrap_or_default();
+        let boxes = bboxes
+            .iter()
+            .map(|bbox| BoundingBox {
                 class: bbox.name().unwrap_or("unknown").to_string(),
                 confidence: bbox.confidence(),
                 x: bbox.x(),
                 y: bbox.y(),
                 width: bbox.w(),
                 height: bbox.h(),
-            });
+            })
+            .collect();
+
+        let rotated_boxes = y
+            .mbrs()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|mbr| {
+                let vertices = mbr
+                    .vertices()
+                    .iter()
+                    .map(|vertex| [vertex.x as f32, vertex.y as f32])
+                    .collect::<Vec<_>>();
+                Some(RotatedBox {
+                    class: mbr.name().unwrap_or("unknown").to_string(),
+                    confidence: mbr.confidence(),
+                    // Skip anything that isn't a quadrilateral
+                    vertices: vertices.try_into().ok()?,
+                })
+            })
+            .collect();
+
+        let polygons = y
+            .polygons()
+            .unwrap_or_default()
+            .iter()
+            .map(|polygon| Polygon {
+                class: polygon.name().unwrap_or("unknown").to_string(),
+                confidence: polygon.confidence(),
+                points: polygon
+                    .polygon()
+                    .exterior()
+                    .coords()
+                    .map(|point| [point.x as f32, point.y as f32])
+                    .collect(),
+            })
+            .collect();
+
+        // usls masks carry no score, but segmentation models return them in the same order
+        // as the boxes
+        let masks = y
+            .masks()
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(i, mask)| {
+                let image = mask.mask();
+                Mask {
+                    class: mask.name().unwrap_or("unknown").to_string(),
+                    confidence: bboxes.get(i).map_or(1.0, |bbox| bbox.confidence()),
+                    width: image.width(),
+                    height: image.height(),
+                    data: Arc::new(image.as_raw().clone()),
+                }
+            })
+            .collect();
+
+        let keypoints = y
+            .keypoints()
+            .unwrap_or_default()
+            .iter()
+            .map(|instance| {
+                instance
+                    .iter()
+                    .map(|keypoint| Keypoint {
+                        x: keypoint.x(),
+                        y: keypoint.y(),
+                        confidence: keypoint.confidence(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Detections {
+            boxes,
+            rotated_boxes,
+            polygons,
+            masks,
+            keypoints,
         }
-        Detections { boxes }
     }
 }
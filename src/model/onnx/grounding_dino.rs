@@ -0,0 +1,43 @@
+use anyhow::Result;
+use image::DynamicImage;
+use usls::{GroundingDINO, Options, Xs};
+
+use super::UslsModel;
+use crate::backend::DetectionParams;
+use crate::model::ModelType;
+
+impl UslsModel for GroundingDINO {
+    const MODEL_TYPE: ModelType = ModelType::GroundingDINO;
+
+    fn options(params: &DetectionParams) -> Options {
+        let class_names = params
+            .class_names
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>();
+        Options::grounding_dino()
+            .with_text_names(&class_names)
+            .with_class_confs(&[DetectionParams::CONFIDENCE_FLOOR])
+            .with_text_confs(&[params.text_confidence])
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        GroundingDINO::new(options)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        GroundingDINO::preprocess(self, images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        GroundingDINO::inference(self, xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        GroundingDINO::postprocess(self, xs)
+    }
+
+    fn spec(&self) -> &str {
+        GroundingDINO::spec(self)
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use image::DynamicImage;
+use usls::{OWLv2, Options, Xs};
+
+use super::UslsModel;
+use crate::backend::DetectionParams;
+use crate::model::ModelType;
+
+impl UslsModel for OWLv2 {
+    const MODEL_TYPE: ModelType = ModelType::OWLv2;
+
+    fn options(params: &DetectionParams) -> Options {
+        let class_names = params
+            .class_names
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>();
+        // OWLv2 has no separate text threshold, prompts are only matched by the class confidence
+        Options::owlv2()
+            .with_class_names(&class_names)
+            .with_class_confs(&[DetectionParams::CONFIDENCE_FLOOR])
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        OWLv2::new(options)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        OWLv2::preprocess(self, images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        OWLv2::inference(self, xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        OWLv2::postprocess(self, xs)
+    }
+
+    fn spec(&self) -> &str {
+        OWLv2::spec(self)
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+use image::DynamicImage;
+use usls::{Options, Xs, RTDETR};
+
+use super::UslsModel;
+use crate::backend::DetectionParams;
+use crate::model::ModelType;
+
+impl UslsModel for RTDETR {
+    const MODEL_TYPE: ModelType = ModelType::RTDETR;
+
+    fn options(_params: &DetectionParams) -> Options {
+        Options::rtdetr()
+            .with_class_names(&usls::COCO_CLASS_NAMES_80)
+            .with_class_confs(&[DetectionParams::CONFIDENCE_FLOOR])
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        RTDETR::new(options)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        RTDETR::preprocess(self, images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        RTDETR::inference(self, xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        RTDETR::postprocess(self, xs)
+    }
+
+    fn spec(&self) -> &str {
+        RTDETR::spec(self)
+    }
+}
@@ -0,0 +1,106 @@
+//! The YOLO family, one [`UslsModel`] per task since they need different weights
+
+use anyhow::Result;
+use image::DynamicImage;
+use usls::{Options, Task, Xs, YOLO};
+
+use super::UslsModel;
+use crate::backend::DetectionParams;
+use crate::model::ModelType;
+
+fn options(task: Task) -> Options {
+    Options::yolo()
+        .with_model_task(task)
+        .with_class_names(&usls::COCO_CLASS_NAMES_80)
+        .with_class_confs(&[DetectionParams::CONFIDENCE_FLOOR])
+}
+
+pub struct YoloDetect(YOLO);
+
+impl UslsModel for YoloDetect {
+    const MODEL_TYPE: ModelType = ModelType::Yolo;
+
+    fn options(_params: &DetectionParams) -> Options {
+        options(Task::ObjectDetection)
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        YOLO::new(options).map(Self)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        self.0.preprocess(images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        self.0.inference(xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        self.0.postprocess(xs)
+    }
+
+    fn spec(&self) -> &str {
+        self.0.spec()
+    }
+}
+
+pub struct YoloSeg(YOLO);
+
+impl UslsModel for YoloSeg {
+    const MODEL_TYPE: ModelType = ModelType::YoloSeg;
+
+    fn options(_params: &DetectionParams) -> Options {
+        options(Task::InstanceSegmentation)
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        YOLO::new(options).map(Self)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        self.0.preprocess(images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        self.0.inference(xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        self.0.postprocess(xs)
+    }
+
+    fn spec(&self) -> &str {
+        self.0.spec()
+    }
+}
+
+pub struct YoloPose(YOLO);
+
+impl UslsModel for YoloPose {
+    const MODEL_TYPE: ModelType = ModelType::YoloPose;
+
+    fn options(_params: &DetectionParams) -> Options {
+        options(Task::KeypointsDetection).with_keypoint_names(&usls::COCO_KEYPOINTS_NAMES_17)
+    }
+
+    fn build(options: Options) -> Result<Self> {
+        YOLO::new(options).map(Self)
+    }
+
+    fn preprocess(&mut self, images: &[DynamicImage]) -> Result<Xs> {
+        self.0.preprocess(images)
+    }
+
+    fn inference(&mut self, xs: Xs) -> Result<Xs> {
+        self.0.inference(xs)
+    }
+
+    fn postprocess(&mut self, xs: Xs) -> Result<Vec<usls::Y>> {
+        self.0.postprocess(xs)
+    }
+
+    fn spec(&self) -> &str {
+        self.0.spec()
+    }
+}
@@ -0,0 +1,436 @@
+//! Clean-up of the model output, applied by the model after each detection and before the
+//! annotated image is drawn.
+//!
+//! Open-vocabulary models often find the same object for several prompts, or several boxes
+//! for one object. The steps run in this order: synonym merge, area filter, NMS, top-K.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{BoundingBox, Detections};
+
+/// Non-maximum suppression of overlapping boxes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Nms {
+    #[default]
+    Off,
+    /// Only boxes of the same class suppress each other
+    ClassAware,
+    /// Any overlapping boxes suppress each other, whatever their class
+    ClassAgnostic,
+}
+
+impl Nms {
+    pub const ALL: [Nms; 3] = [Nms::Off, Nms::ClassAware, Nms::ClassAgnostic];
+}
+
+impl std::fmt::Display for Nms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Off => "No NMS",
+            Self::ClassAware => "Class-aware NMS",
+            Self::ClassAgnostic => "Class-agnostic NMS",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessing {
+    pub nms: Nms,
+    /// Boxes overlapping a better one by more than this are suppressed
+    pub iou_threshold: f32,
+    /// Smallest box area to keep, in image pixels
+    pub min_area: Option<f32>,
+    /// Largest box area to keep, in image pixels
+    pub max_area: Option<f32>,
+    /// Most boxes to keep for each class, the most confident ones
+    pub top_k: Option<usize>,
+    /// Classes to rename, e.g. "automobile" to "car", so synonymous prompts count as one class
+    pub synonyms: BTreeMap<String, String>,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            nms: Nms::Off,
+            iou_threshold: 0.5,
+            min_area: None,
+            max_area: None,
+            top_k: None,
+            synonyms: BTreeMap::new(),
+        }
+    }
+}
+
+impl PostProcessing {
+    /// Clean up the detections.
+    ///
+    /// Filters and NMS work on the boxes. Masks, polygons and keypoints are kept for the same
+    /// boxes when the model gives one per box, and left as they are otherwise.
+    pub fn apply(&self, detections: Detections) -> Detections {
+        self.apply_with_indices(detections).0
+    }
+
+    /// Clean up the detections like [`Self::apply`], also returning the indices of the boxes
+    /// kept, in the model's order
+    pub fn apply_with_indices(&self, mut detections: Detections) -> (Detections, Vec<usize>) {
+        if !self.synonyms.is_empty() {
+            self.merge_synonyms(&mut detections);
+        }
+
+        let boxes = &detections.boxes;
+        let mut keep = (0..boxes.len())
+            .filter(|&i| {
+                let area = boxes[i].width * boxes[i].height;
+                self.min_area.is_none_or(|min| area >= min)
+                    && self.max_area.is_none_or(|max| area <= max)
+            })
+            .collect::<Vec<_>>();
+        // Most confident first, so the best boxes win
+        keep.sort_by(|&a, &b| boxes[b].confidence.total_cmp(&boxes[a].confidence));
+
+        if self.nms != Nms::Off {
+            keep = self.suppress(boxes, &keep);
+        }
+        if let Some(top_k) = self.top_k {
+            let mut counts = HashMap::<&str, usize>::new();
+            keep.retain(|&i| {
+                let count = counts.entry(&boxes[i].class).or_default();
+                *count += 1;
+                *count <= top_k
+            });
+        }
+
+        // Back in the model's order
+        keep.sort_unstable();
+        (select(detections, &keep), keep)
+    }
+
+    /// The class a class is merged into, itself if it has no synonym
+    pub fn merged_class<'a>(&'a self, class: &'a str) -> &'a str {
+        self.synonyms.get(class).map_or(class, String::as_str)
+    }
+
+    fn merge_synonyms(&self, detections: &mut Detections) {
+        let classes = detections
+            .boxes
+            .iter_mut()
+            .map(|bbox| &mut bbox.class)
+            .chain(
+                detections
+                    .rotated_boxes
+                    .iter_mut()
+                    .map(|rotated| &mut rotated.class),
+            )
+            .chain(
+                detections
+                    .polygons
+                    .iter_mut()
+                    .map(|polygon| &mut polygon.class),
+            )
+            .chain(detections.masks.iter_mut().map(|mask| &mut mask.class));
+        for class in classes {
+            if let Some(merged) = self.synonyms.get(class.as_str()) {
+                class.clone_from(merged);
+            }
+        }
+    }
+
+    /// Greedy NMS over `order`, which is sorted by confidence
+    fn suppress(&self, boxes: &[BoundingBox], order: &[usize]) -> Vec<usize> {
+        let mut kept: Vec<usize> = Vec::with_capacity(order.len());
+        for &i in order {
+            let suppressed = kept.iter().any(|&j| {
+                (self.nms == Nms::ClassAgnostic || boxes[i].class == boxes[j].class)
+                    && iou(&boxes[i], &boxes[j]) > self.iou_threshold
+            });
+            if !suppressed {
+                kept.push(i);
+            }
+        }
+        kept
+    }
+}
+
+/// Read synonyms written as `car = automobile, auto; person = man, woman`, into a map from each
+/// synonym to the class it is merged into. Groups without a `=` are skipped.
+pub fn parse_synonyms(text: &str) -> BTreeMap<String, String> {
+    let mut synonyms = BTreeMap::new();
+    for group in text.split(';') {
+        let Some((class, names)) = group.split_once('=') else {
+            continue;
+        };
+        let class = class.trim();
+        if class.is_empty() {
+            continue;
+        }
+        for name in names.split(',').map(str::trim) {
+            if !name.is_empty() && name != class {
+                synonyms.insert(name.to_string(), class.to_string());
+            }
+        }
+    }
+    synonyms
+}
+
+fn iou(a: &BoundingBox, b: &BoundingBox) -> f32 {
+    let width = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+    let height = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+    let intersection = width.max(0.0) * height.max(0.0);
+    let union = a.width * a.height + b.width * b.height - intersection;
+    if union > 0.0 {
+        intersection / union
+    } else {
+        0.0
+    }
+}
+
+/// The detections for the boxes at `keep`
+fn select(detections: Detections, keep: &[usize]) -> Detections {
+    let count = detections.boxes.len();
+    // Only pick from outputs that have one entry per box
+    fn pick<T: Clone>(items: Vec<T>, keep: &[usize], count: usize) -> Vec<T> {
+        if items.len() == count {
+            keep.iter().map(|&i| items[i].clone()).collect()
+        } else {
+            items
+        }
+    }
+
+    Detections {
+        boxes: pick(detections.boxes, keep, count),
+        rotated_boxes: detections.rotated_boxes,
+        polygons: pick(detections.polygons, keep, count),
+        masks: pick(detections.masks, keep, count),
+        keypoints: pick(detections.keypoints, keep, count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::model::{Keypoint, Mask};
+
+    fn bbox(class: &str, confidence: f32, x: f32, y: f32, width: f32, height: f32) -> BoundingBox {
+        BoundingBox {
+            class: class.to_string(),
+            confidence,
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn detections(boxes: Vec<BoundingBox>) -> Detections {
+        Detections {
+            boxes,
+            ..Default::default()
+        }
+    }
+
+    fn confidences(detections: &Detections) -> Vec<f32> {
+        detections
+            .boxes
+            .iter()
+            .map(|bbox| bbox.confidence)
+            .collect()
+    }
+
+    /// A cat, a dog on top of it, and a second cat mostly overlapping the first
+    fn overlapping() -> Detections {
+        detections(vec![
+            bbox("cat", 0.9, 0.0, 0.0, 10.0, 10.0),
+            bbox("dog", 0.8, 1.0, 0.0, 10.0, 10.0),
+            bbox("cat", 0.7, 0.0, 1.0, 10.0, 10.0),
+        ])
+    }
+
+    #[test]
+    fn off_keeps_everything() {
+        let processed = PostProcessing::default().apply(overlapping());
+        assert_eq!(confidences(&processed), [0.9, 0.8, 0.7]);
+    }
+
+    #[test]
+    fn class_aware_nms_only_suppresses_the_same_class() {
+        let post_processing = PostProcessing {
+            nms: Nms::ClassAware,
+            ..Default::default()
+        };
+        let processed = post_processing.apply(overlapping());
+        assert_eq!(confidences(&processed), [0.9, 0.8]);
+    }
+
+    #[test]
+    fn class_agnostic_nms_suppresses_any_class() {
+        let post_processing = PostProcessing {
+            nms: Nms::ClassAgnostic,
+            ..Default::default()
+        };
+        let processed = post_processing.apply(overlapping());
+        assert_eq!(confidences(&processed), [0.9]);
+    }
+
+    #[test]
+    fn nms_keeps_boxes_below_the_iou_threshold() {
+        let post_processing = PostProcessing {
+            nms: Nms::ClassAgnostic,
+            iou_threshold: 0.9,
+            ..Default::default()
+        };
+        let processed = post_processing.apply(overlapping());
+        assert_eq!(confidences(&processed), [0.9, 0.8, 0.7]);
+    }
+
+    #[test]
+    fn indices_of_kept_boxes_are_in_the_models_order() {
+        let post_processing = PostProcessing {
+            nms: Nms::ClassAware,
+            synonyms: parse_synonyms("pet = cat, dog"),
+            ..Default::default()
+        };
+        let (processed, kept) = post_processing.apply_with_indices(detections(vec![
+            bbox("cat", 0.5, 40.0, 0.0, 10.0, 10.0),
+            bbox("cat", 0.7, 0.0, 1.0, 10.0, 10.0),
+            bbox("dog", 0.9, 0.0, 0.0, 10.0, 10.0),
+        ]));
+        assert_eq!(kept, [0, 2]);
+        assert_eq!(confidences(&processed), [0.5, 0.9]);
+        assert_eq!(post_processing.merged_class("dog"), "pet");
+        assert_eq!(post_processing.merged_class("bus"), "bus");
+    }
+
+    #[test]
+    fn iou_of_boxes() {
+        let a = bbox("cat", 1.0, 0.0, 0.0, 10.0, 10.0);
+        let b = bbox("cat", 1.0, 5.0, 0.0, 10.0, 10.0);
+        let apart = bbox("cat", 1.0, 20.0, 20.0, 10.0, 10.0);
+        assert_eq!(iou(&a, &a), 1.0);
+        assert!((iou(&a, &b) - 50.0 / 150.0).abs() < 1e-6);
+        assert_eq!(iou(&a, &apart), 0.0);
+    }
+
+    #[test]
+    fn iou_of_zero_area_boxes() {
+        let point = bbox("cat", 1.0, 5.0, 5.0, 0.0, 0.0);
+        let a = bbox("cat", 1.0, 0.0, 0.0, 10.0, 10.0);
+        assert_eq!(iou(&point, &point), 0.0);
+        assert_eq!(iou(&point, &a), 0.0);
+        assert_eq!(iou(&a, &point), 0.0);
+    }
+
+    #[test]
+    fn top_k_keeps_the_most_confident_of_each_class() {
+        let post_processing = PostProcessing {
+            top_k: Some(1),
+            ..Default::default()
+        };
+        let processed = post_processing.apply(detections(vec![
+            bbox("cat", 0.5, 0.0, 0.0, 10.0, 10.0),
+            bbox("dog", 0.6, 20.0, 0.0, 10.0, 10.0),
+            bbox("cat", 0.9, 40.0, 0.0, 10.0, 10.0),
+        ]));
+        // In the model's order, not by confidence
+        assert_eq!(confidences(&processed), [0.6, 0.9]);
+    }
+
+    #[test]
+    fn area_filters() {
+        let boxes = detections(vec![
+            bbox("cat", 0.1, 0.0, 0.0, 2.0, 2.0),
+            bbox("cat", 0.2, 0.0, 0.0, 5.0, 5.0),
+            bbox("cat", 0.3, 0.0, 0.0, 10.0, 10.0),
+        ]);
+        let min = PostProcessing {
+            min_area: Some(25.0),
+            ..Default::default()
+        };
+        assert_eq!(confidences(&min.apply(boxes.clone())), [0.2, 0.3]);
+        let max = PostProcessing {
+            max_area: Some(25.0),
+            ..Default::default()
+        };
+        assert_eq!(confidences(&max.apply(boxes.clone())), [0.1, 0.2]);
+        let both = PostProcessing {
+            min_area: Some(10.0),
+            max_area: Some(50.0),
+            ..Default::default()
+        };
+        assert_eq!(confidences(&both.apply(boxes)), [0.2]);
+    }
+
+    #[test]
+    fn synonyms_are_parsed() {
+        let synonyms =
+            parse_synonyms(" car = automobile, auto ;person=man,, woman,person; =x; dog");
+        assert_eq!(
+            synonyms,
+            BTreeMap::from(
+                [
+                    ("automobile", "car"),
+                    ("auto", "car"),
+                    ("man", "person"),
+                    ("woman", "person"),
+                ]
+                .map(|(name, class)| (name.to_string(), class.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn synonyms_merge_classes_before_nms() {
+        let post_processing = PostProcessing {
+            nms: Nms::ClassAware,
+            synonyms: parse_synonyms("cat = dog"),
+            ..Default::default()
+        };
+        let processed = post_processing.apply(overlapping());
+        assert_eq!(confidences(&processed), [0.9]);
+        assert_eq!(processed.boxes[0].class, "cat");
+    }
+
+    fn mask(confidence: f32) -> Mask {
+        Mask {
+            class: "cat".to_string(),
+            confidence,
+            width: 1,
+            height: 1,
+            data: Arc::new(vec![255]),
+        }
+    }
+
+    fn keypoint(confidence: f32) -> Vec<Keypoint> {
+        vec![Keypoint {
+            x: 0.0,
+            y: 0.0,
+            confidence,
+        }]
+    }
+
+    #[test]
+    fn select_keeps_masks_and_keypoints_with_their_boxes() {
+        let mut detections = overlapping();
+        detections.masks = vec![mask(0.9), mask(0.8), mask(0.7)];
+        detections.keypoints = vec![keypoint(0.9), keypoint(0.8), keypoint(0.7)];
+
+        let selected = select(detections, &[0, 2]);
+        assert_eq!(confidences(&selected), [0.9, 0.7]);
+        let masks = selected.masks.iter().map(|mask| mask.confidence);
+        assert_eq!(masks.collect::<Vec<_>>(), [0.9, 0.7]);
+        let keypoints = selected.keypoints.iter().map(|pose| pose[0].confidence);
+        assert_eq!(keypoints.collect::<Vec<_>>(), [0.9, 0.7]);
+    }
+
+    #[test]
+    fn select_leaves_outputs_that_are_not_per_box() {
+        let mut detections = overlapping();
+        detections.masks = vec![mask(0.9)];
+
+        let selected = select(detections, &[1]);
+        assert_eq!(confidences(&selected), [0.8]);
+        assert_eq!(selected.masks.len(), 1);
+        assert_eq!(selected.masks[0].confidence, 0.9);
+    }
+}
@@ -0,0 +1,166 @@
+//! Where to find the weights and related files for each model.
+//!
+//! The built-in defaults can be overridden per model from a JSON file, looked up in this order:
+//! an explicit path (the `--model-config` flag), the `ZEROSHOT_MODEL_CONFIG` environment variable,
+//! and `models.json` in the working directory. Models the file leaves out keep their defaults.
+//!
+//! The default paths are relative to the weights directory, `ZEROSHOT_WEIGHTS_DIR` if set or else
+//! `weights` in the working directory. Relative paths in the file are resolved against
+//! `ZEROSHOT_WEIGHTS_DIR` if set, or else the directory of the config file.
+//!
+//! ```json
+//! {
+//!   "models": {
+//!     "grounding-dino": {
+//!       "model_file": "weights/grounding-dino/swint-ogc.onnx",
+//!       "dtype": "fp32"
+//!     }
+//!   }
+//! }
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::{DType, ModelType};
+use crate::backend::BackendError;
+
+pub const CONFIG_ENV: &str = "ZEROSHOT_MODEL_CONFIG";
+pub const WEIGHTS_DIR_ENV: &str = "ZEROSHOT_WEIGHTS_DIR";
+const DEFAULT_CONFIG_FILE: &str = "models.json";
+const DEFAULT_WEIGHTS_DIR: &str = "weights";
+
+/// Where the default weights are looked for
+fn weights_dir() -> PathBuf {
+    std::env::var_os(WEIGHTS_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_WEIGHTS_DIR))
+}
+
+/// Files and settings needed to load a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub model_file: PathBuf,
+    #[serde(default)]
+    pub dtype: DType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_file: Option<PathBuf>,
+}
+
+impl ModelConfig {
+    pub fn new(model_file: impl Into<PathBuf>) -> Self {
+        Self {
+            model_file: model_file.into(),
+            dtype: DType::default(),
+            tokenizer_file: None,
+            config_file: None,
+        }
+    }
+
+    /// Check that all the configured files exist
+    pub fn validate(&self) -> Result<(), BackendError> {
+        let files = std::iter::once(&self.model_file)
+            .chain(&self.tokenizer_file)
+            .chain(&self.config_file);
+        for file in files {
+            if !file.exists() {
+                return Err(BackendError::ModelFileMissing(file.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, base_dir: &Path) {
+        // Joining an absolute path replaces the base
+        self.model_file = base_dir.join(&self.model_file);
+        for file in [&mut self.tokenizer_file, &mut self.config_file]
+            .into_iter()
+            .flatten()
+        {
+            *file = base_dir.join(&*file);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelRegistry {
+    pub models: BTreeMap<ModelType, ModelConfig>,
+}
+
+impl Default for ModelRegistry {
+    /// The built-in weights, in the weights directory
+    fn default() -> Self {
+        let mut models = BTreeMap::from([
+            (
+                ModelType::GroundingDINO,
+                ModelConfig::new("grounding-dino/swint-ogc.onnx"),
+            ),
+            (
+                ModelType::OWLv2,
+                ModelConfig::new("owlv2/base-ensemble.onnx"),
+            ),
+            (ModelType::Yolo, ModelConfig::new("yolo/v8-m-det.onnx")),
+            (ModelType::YoloSeg, ModelConfig::new("yolo/v8-m-seg.onnx")),
+            (ModelType::YoloPose, ModelConfig::new("yolo/v8-m-pose.onnx")),
+            (ModelType::RTDETR, ModelConfig::new("rtdetr/v2-s-coco.onnx")),
+        ]);
+        let weights_dir = weights_dir();
+        for config in models.values_mut() {
+            config.resolve(&weights_dir);
+        }
+        Self { models }
+    }
+}
+
+impl ModelRegistry {
+    /// Load the registry, see the module docs for where it is looked for
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+
+        let mut registry = Self::default();
+        let Some(path) = path else {
+            return Ok(registry);
+        };
+
+        log::info!("Reading model config from {}", path.display());
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let overrides: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid model config {}", path.display()))?;
+        let base_dir = std::env::var_os(WEIGHTS_DIR_ENV)
+            .map(PathBuf::from)
+            .or_else(|| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        for (model, mut config) in overrides.models {
+            config.resolve(&base_dir);
+            registry.set(model, config);
+        }
+
+        Ok(registry)
+    }
+
+    pub fn get(&self, model: &ModelType) -> Option<&ModelConfig> {
+        self.models.get(model)
+    }
+
+    pub fn set(&mut self, model: ModelType, config: ModelConfig) {
+        self.models.insert(model, config);
+    }
+
+    /// Check that the files for a model are configured and exist
+    pub fn validate(&self, model: &ModelType) -> Result<(), BackendError> {
+        if !model.needs_weights() {
+            return Ok(());
+        }
+        self.get(model)
+            .ok_or_else(|| BackendError::ModelNotConfigured(model.clone()))?
+            .validate()
+    }
+}
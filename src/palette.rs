@@ -0,0 +1,39 @@
+//! Colors for classes, shared by the GUI overlay and the annotated images saved by usls
+
+/// The usls annotator's palette, so a class gets the same color in the GUI and in saved images
+pub const PALETTE: [[u8; 3]; 20] = [
+    [0, 255, 127],
+    [255, 105, 180],
+    [255, 99, 71],
+    [255, 215, 0],
+    [188, 143, 143],
+    [0, 191, 255],
+    [143, 188, 143],
+    [238, 130, 238],
+    [154, 205, 50],
+    [205, 133, 63],
+    [30, 144, 255],
+    [112, 128, 144],
+    [127, 255, 212],
+    [51, 153, 255],
+    [0, 255, 255],
+    [138, 43, 226],
+    [165, 42, 42],
+    [216, 191, 216],
+    [240, 255, 255],
+    [95, 158, 160],
+];
+
+/// Index in [`PALETTE`] for a class name, the same on every run.
+///
+/// Uses FNV-1a, as the std hasher's output may change between Rust releases.
+pub fn class_index(class: &str) -> usize {
+    let hash = class.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash % PALETTE.len() as u64) as usize
+}
+
+pub fn class_rgb(class: &str) -> [u8; 3] {
+    PALETTE[class_index(class)]
+}
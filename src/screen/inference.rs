@@ -1,22 +1,35 @@
+pub mod annotations;
+
 // use crate::backend::{Input, Output};
 use crate::backend;
-use crate::frontend::{Message, ZeroShotRust};
-// use crate::io;
+use crate::frontend::{BackendNotice, Message, ZeroShotRust};
+use crate::io;
+use crate::model::postprocess::Nms;
+use crate::model::{
+    BoundingBox, Capabilities, DType, DetectionResults, Detections, Mask, Timings, COCO_SKELETON,
+};
+use crate::palette;
+use annotations::{Annotations, BoxEdit};
 
 // use std::sync::Arc;
 // use iced::border;
 // use iced::keyboard;
+use futures::StreamExt;
 use iced::widget::canvas::{Path, Stroke};
 use iced::widget::{
-    button, canvas, center, checkbox, column, container, horizontal_space, pick_list, row,
-    scrollable, text, Space,
+    button, canvas, center, checkbox, column, container, horizontal_space, opaque, pick_list,
+    progress_bar, row, scrollable, slider, stack, text, text_input, Space,
 };
-use iced::{mouse, Vector};
-// use iced::Size;
 use iced::{
-    color, Center, Element, Fill, Font, Length, Point, Rectangle, Renderer, Subscription, Task,
-    Theme,
+    color, Center, Color, Element, Fill, Font, Length, Point, Rectangle, Renderer, Size,
+    Subscription, Task, Theme,
 };
+use iced::{event, mouse, Radians, Vector};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::time::error::Elapsed;
 
 // use futures::{SinkExt, Stream, StreamExt};
@@ -100,134 +113,1713 @@ fn square<'a>(
     canvas(Square).width(width).height(height).into()
 }
 
+/// A mask colored in for drawing on top of the image
+#[derive(Debug, Clone)]
+struct MaskOverlay {
+    data: Arc<Vec<u8>>,
+    handle: iced::advanced::image::Handle,
+}
+
+impl MaskOverlay {
+    const OPACITY: f32 = 0.45;
+
+    fn new(mask: &Mask) -> Self {
+        let [r, g, b, _] = class_color(&mask.class).into_rgba8();
+        let rgba = mask
+            .data
+            .iter()
+            .flat_map(|&coverage| [r, g, b, (coverage as f32 * Self::OPACITY) as u8])
+            .collect::<Vec<_>>();
+        Self {
+            data: mask.data.clone(),
+            handle: iced::advanced::image::Handle::from_rgba(mask.width, mask.height, rgba),
+        }
+    }
+}
+
+/// How to size the image in the canvas, until the user zooms or pans
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoomMode {
+    /// Show the whole image, never scaling it up
+    Fit,
+    /// One image pixel per screen pixel
+    Actual,
+    /// Cover the whole canvas, cropping the image
+    Fill,
+}
+
+/// Placement of the image in the canvas, with `origin` being the image's top-left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transform {
+    origin: Point,
+    /// Screen pixels per image pixel
+    scale: f32,
+}
+
+impl Transform {
+    const MIN_SCALE: f32 = 0.05;
+    const MAX_SCALE: f32 = 32.0;
+
+    fn to_canvas(&self, [x, y]: [f32; 2]) -> Point {
+        Point::new(
+            self.origin.x + x * self.scale,
+            self.origin.y + y * self.scale,
+        )
+    }
+
+    fn to_image(&self, point: Point) -> Point {
+        Point::ORIGIN + (point - self.origin) * (1.0 / self.scale)
+    }
+
+    /// Zoom by `factor` keeping the image point under `anchor` in place
+    fn zoom(&self, factor: f32, anchor: Point) -> Self {
+        let scale = (self.scale * factor).clamp(Self::MIN_SCALE, Self::MAX_SCALE);
+        let ratio = scale / self.scale;
+        Self {
+            origin: anchor - (anchor - self.origin) * ratio,
+            scale,
+        }
+    }
+}
+
+/// Which classes are drawn and listed
+#[derive(Debug, Clone, Default)]
+pub struct ClassFilter {
+    pub hidden: HashSet<String>,
+    /// Show this class alone, whatever is hidden
+    pub only: Option<String>,
+}
+
+impl ClassFilter {
+    pub fn shows(&self, class: &str) -> bool {
+        match &self.only {
+            Some(only) => only == class,
+            None => !self.hidden.contains(class),
+        }
+    }
+}
+
+/// Zoom and pan of the canvas, kept by iced between redraws
+#[derive(Debug, Default)]
+pub struct Viewport {
+    /// Set once the user zooms or pans, until then the zoom mode decides
+    transform: Option<Transform>,
+    /// The zoom request `transform` belongs to, an older one means it is stale
+    request: u64,
+    /// Last cursor position while panning
+    drag: Option<Point>,
+    /// Where the button went down, to tell a click from a drag
+    press: Option<Point>,
+    /// Index of the box under the cursor
+    hovered: Option<usize>,
+    /// Box being drawn, moved or resized in edit mode
+    edit: Option<BoxDrag>,
+}
+
+/// A box being drawn, moved or resized in edit mode, in image pixel coordinates
+#[derive(Debug, Clone, Copy)]
+enum BoxDrag {
+    Draw {
+        start: Point,
+    },
+    Move {
+        index: usize,
+        /// Where the box was grabbed
+        grab: Point,
+        original: Rectangle,
+    },
+    /// Resize by dragging a corner, with `anchor` being the opposite corner
+    Resize {
+        index: usize,
+        anchor: Point,
+    },
+}
+
+impl BoxDrag {
+    /// The box being edited, if it is an existing one
+    fn index(&self) -> Option<usize> {
+        match *self {
+            BoxDrag::Draw { .. } => None,
+            BoxDrag::Move { index, .. } | BoxDrag::Resize { index, .. } => Some(index),
+        }
+    }
+
+    /// The box with the cursor at `point`
+    fn rect(&self, point: Point) -> Rectangle {
+        match *self {
+            BoxDrag::Draw { start } | BoxDrag::Resize { anchor: start, .. } => Rectangle::new(
+                Point::new(start.x.min(point.x), start.y.min(point.y)),
+                Size::new((start.x - point.x).abs(), (start.y - point.y).abs()),
+            ),
+            BoxDrag::Move { grab, original, .. } => Rectangle {
+                x: original.x + point.x - grab.x,
+                y: original.y + point.y - grab.y,
+                ..original
+            },
+        }
+    }
+}
+
+fn box_rect(bbox: &BoundingBox) -> Rectangle {
+    Rectangle::new(
+        Point::new(bbox.x, bbox.y),
+        Size::new(bbox.width, bbox.height),
+    )
+}
+
+/// Corners of a box going round from the top-left, so the opposite of corner `i` is `(i + 2) % 4`
+fn corners(rect: Rectangle) -> [Point; 4] {
+    [
+        Point::new(rect.x, rect.y),
+        Point::new(rect.x + rect.width, rect.y),
+        Point::new(rect.x + rect.width, rect.y + rect.height),
+        Point::new(rect.x, rect.y + rect.height),
+    ]
+}
+
+/// An image converted for display once, along with halved copies to draw when zoomed out.
+/// Cheap to clone, the pixels are shared.
+#[derive(Debug, Clone)]
+pub struct DisplayImage {
+    width: u32,
+    height: u32,
+    /// Full size first, each following level half the size of the one before
+    levels: Arc<[iced::advanced::image::Handle]>,
+}
+
+impl DisplayImage {
+    /// Images are halved until their longest side is at most this
+    const PREVIEW_SIZE: u32 = 2048;
+
+    pub fn new(image: &image::DynamicImage) -> Self {
+        let mut level = image.to_rgba8();
+        let (width, height) = level.dimensions();
+        let mut levels = vec![];
+        loop {
+            let (level_width, level_height) = level.dimensions();
+            let next = (level_width.max(level_height) > Self::PREVIEW_SIZE).then(|| {
+                image::imageops::resize(
+                    &level,
+                    level_width / 2,
+                    level_height / 2,
+                    image::imageops::FilterType::Triangle,
+                )
+            });
+            levels.push(iced::advanced::image::Handle::from_rgba(
+                level_width,
+                level_height,
+                level.into_raw(),
+            ));
+            match next {
+                Some(next) => level = next,
+                None => break,
+            }
+        }
+        Self {
+            width,
+            height,
+            levels: levels.into(),
+        }
+    }
+
+    /// Convert on a blocking thread, to keep large images from stalling the UI
+    pub async fn load(image: Arc<image::DynamicImage>) -> Self {
+        tokio::task::spawn_blocking(move || Self::new(&image))
+            .await
+            .expect("Failed to convert image for display")
+    }
+
+    /// An encoded image, left for iced to decode
+    fn encoded(bytes: &'static [u8]) -> Self {
+        let (width, height) = image::ImageReader::new(std::io::Cursor::new(bytes))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok())
+            .unwrap_or((640, 480));
+        Self {
+            width,
+            height,
+            levels: Arc::new([iced::advanced::image::Handle::from_bytes(bytes)]),
+        }
+    }
+
+    /// The smallest level still sharp when drawn at `scale` screen pixels per image pixel
+    fn level(&self, scale: f32) -> &iced::advanced::image::Handle {
+        let needed = self.width as f32 * scale;
+        self.levels
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(i, _)| (self.width >> i) as f32 >= needed)
+            .map_or(&self.levels[0], |(_, level)| level)
+    }
+
+    fn smallest(&self) -> &iced::advanced::image::Handle {
+        &self.levels[self.levels.len() - 1]
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Image {
-    image: Option<iced::advanced::image::Handle>,
+    image: Option<DisplayImage>,
+    /// The model's own rendering of the results, shown instead when `show_annotated` is set
+    annotated: Option<DisplayImage>,
+    show_annotated: bool,
     width: u32,
     height: u32,
+    detections: Detections,
+    overlays: Vec<MaskOverlay>,
+    /// Index of the selected box
+    selected: Option<usize>,
+    zoom: ZoomMode,
+    /// Identifies the last zoom request, so the canvas drops the user's zoom and pan when it changes
+    zoom_request: u64,
+    /// Whether dragging draws and reshapes boxes instead of panning
+    editing: bool,
+    classes: ClassFilter,
 }
 
 impl Image {
-    pub fn new(image: &image::DynamicImage) -> Self {
-        let rgba = image.to_rgba8(); // copies image!
-        let handle = iced::advanced::image::Handle::from_rgba(
-            rgba.width(),
-            rgba.height(),
-            rgba.as_raw().to_vec(),
-        );
+    /// Shows `image`, or nothing until it is decoded, making room for an image of `size`
+    pub fn new(image: Option<&DisplayImage>, size: Option<(u32, u32)>) -> Self {
+        let (width, height) = image
+            .map(|image| (image.width, image.height))
+            .or(size)
+            .unwrap_or((640, 480));
         Self {
-            image: Some(handle),
-            width: rgba.width(),
-            height: rgba.height(),
+            image: image.cloned(),
+            annotated: None,
+            show_annotated: false,
+            width,
+            height,
+            detections: Detections::default(),
+            overlays: vec![],
+            selected: None,
+            zoom: ZoomMode::Fit,
+            zoom_request: Self::next_zoom_request(),
+            editing: false,
+            classes: ClassFilter::default(),
+        }
+    }
+
+    fn next_zoom_request() -> u64 {
+        static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+        NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Reset the zoom and pan to one of the standard sizes
+    pub fn set_zoom(&mut self, zoom: ZoomMode) {
+        self.zoom = zoom;
+        self.zoom_request = Self::next_zoom_request();
+    }
+
+    pub fn set_editing(&mut self, editing: bool) {
+        self.editing = editing;
+    }
+
+    /// Show the image once it is decoded
+    pub fn set_image(&mut self, image: DisplayImage) {
+        self.width = image.width;
+        self.height = image.height;
+        self.image = Some(image);
+    }
+
+    pub fn set_annotated(&mut self, annotated: Option<DisplayImage>) {
+        self.annotated = annotated;
+    }
+
+    pub fn set_show_annotated(&mut self, show: bool) {
+        self.show_annotated = show;
+    }
+
+    pub fn set_class_filter(&mut self, classes: ClassFilter) {
+        self.classes = classes;
+    }
+
+    pub fn detections(&self) -> &Detections {
+        &self.detections
+    }
+
+    pub fn boxes(&self) -> &[BoundingBox] {
+        &self.detections.boxes
+    }
+
+    /// Whether the box at `index` is of a class being shown
+    pub fn is_shown(&self, index: usize) -> bool {
+        self.detections
+            .boxes
+            .get(index)
+            .is_some_and(|bbox| self.classes.shows(&bbox.class))
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn set_selected(&mut self, selected: Option<usize>) {
+        self.selected = selected.filter(|index| *index < self.detections.boxes.len());
+    }
+
+    /// The smallest box containing the canvas position, so nested boxes can still be picked
+    fn box_at(&self, transform: Transform, position: Point) -> Option<usize> {
+        let point = transform.to_image(position);
+        self.detections
+            .boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, bbox)| {
+                self.classes.shows(&bbox.class)
+                    && (bbox.x..=bbox.x + bbox.width).contains(&point.x)
+                    && (bbox.y..=bbox.y + bbox.height).contains(&point.y)
+            })
+            .min_by(|(_, a), (_, b)| (a.width * a.height).total_cmp(&(b.width * b.height)))
+            .map(|(index, _)| index)
+    }
+
+    /// The selected box's corner handle at the canvas position, with the opposite corner
+    fn corner_at(&self, transform: Transform, position: Point) -> Option<(usize, Point)> {
+        const HANDLE_RADIUS: f32 = 6.0;
+
+        let index = self.selected?;
+        let corners = corners(box_rect(self.detections.boxes.get(index)?));
+        let corner = corners.iter().position(|corner| {
+            transform.to_canvas([corner.x, corner.y]).distance(position) <= HANDLE_RADIUS
+        })?;
+        Some((index, corners[(corner + 2) % 4]))
+    }
+
+    /// What pressing the button at the canvas position starts in edit mode: resizing the
+    /// selected box by a corner, moving the box under the cursor, or drawing a new one
+    fn start_box_drag(&self, transform: Transform, position: Point) -> BoxDrag {
+        if let Some((index, anchor)) = self.corner_at(transform, position) {
+            return BoxDrag::Resize { index, anchor };
+        }
+        let point = transform.to_image(position);
+        match self.box_at(transform, position) {
+            Some(index) => BoxDrag::Move {
+                index,
+                grab: point,
+                original: box_rect(&self.detections.boxes[index]),
+            },
+            None => BoxDrag::Draw { start: point },
+        }
+    }
+
+    /// Where a box drag puts the box with the cursor at the image point, kept inside the image
+    fn drag_rect(&self, drag: &BoxDrag, point: Point) -> Rectangle {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let rect = drag.rect(point);
+        match drag {
+            // Moving keeps the size, so push the box back in rather than cropping it
+            BoxDrag::Move { .. } => Rectangle {
+                x: rect.x.clamp(0.0, (width - rect.width).max(0.0)),
+                y: rect.y.clamp(0.0, (height - rect.height).max(0.0)),
+                ..rect
+            },
+            BoxDrag::Draw { .. } | BoxDrag::Resize { .. } => {
+                let x = rect.x.clamp(0.0, width);
+                let y = rect.y.clamp(0.0, height);
+                Rectangle::new(
+                    Point::new(x, y),
+                    Size::new(
+                        ((rect.x + rect.width).min(width) - x).max(0.0),
+                        ((rect.y + rect.height).min(height) - y).max(0.0),
+                    ),
+                )
+            }
         }
     }
+
+    /// The edit made by a finished box drag, ignoring tiny boxes and drags that changed nothing
+    fn finish_box_drag(&self, drag: BoxDrag, point: Point, scale: f32) -> Option<Message> {
+        // In screen pixels, so a slip of the mouse doesn't make a box
+        const MIN_SIZE: f32 = 4.0;
+
+        let rect = self.drag_rect(&drag, point);
+        if rect.width * scale < MIN_SIZE || rect.height * scale < MIN_SIZE {
+            return None;
+        }
+        match drag.index() {
+            None => Some(Message::BoxDrawn(rect)),
+            Some(index) => {
+                let unchanged = self
+                    .detections
+                    .boxes
+                    .get(index)
+                    .is_some_and(|bbox| box_rect(bbox) == rect);
+                (!unchanged).then_some(Message::BoxChanged(index, rect))
+            }
+        }
+    }
+
+    /// Class, confidence and pixel coordinates of a box next to the cursor
+    fn draw_tooltip(&self, frame: &mut canvas::Frame, bbox: &BoundingBox, cursor: Point) {
+        const TEXT_SIZE: f32 = 13.0;
+
+        let lines = [
+            format!("{} {:.2}", bbox.class, bbox.confidence),
+            format!("x {:.0}  y {:.0}", bbox.x, bbox.y),
+            format!("w {:.0}  h {:.0}", bbox.width, bbox.height),
+        ];
+        let line_height = TEXT_SIZE + 4.0;
+        // Approximate the width, the canvas cannot measure text
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0) as f32
+            * TEXT_SIZE
+            * 0.6
+            + 12.0;
+        let height = line_height * lines.len() as f32 + 8.0;
+
+        // Keep the tooltip inside the canvas
+        let bounds = frame.size();
+        let x = (cursor.x + 16.0).min(bounds.width - width).max(0.0);
+        let y = (cursor.y + 16.0).min(bounds.height - height).max(0.0);
+        frame.fill_rectangle(
+            Point::new(x, y),
+            Size::new(width, height),
+            Color::from_rgba(0.0, 0.0, 0.0, 0.8),
+        );
+        for (i, line) in lines.into_iter().enumerate() {
+            frame.fill_text(canvas::Text {
+                content: line,
+                position: Point::new(x + 6.0, y + 4.0 + i as f32 * line_height),
+                color: Color::WHITE,
+                size: TEXT_SIZE.into(),
+                font: Font::MONOSPACE,
+                ..canvas::Text::default()
+            });
+        }
+    }
+
+    pub fn set_detections(&mut self, detections: Detections) {
+        // Coloring a mask touches every pixel, so keep the overlays of masks that are still shown
+        let previous = std::mem::take(&mut self.overlays);
+        self.overlays = detections
+            .masks
+            .iter()
+            .map(|mask| {
+                previous
+                    .iter()
+                    .find(|overlay| Arc::ptr_eq(&overlay.data, &mask.data))
+                    .cloned()
+                    .unwrap_or_else(|| MaskOverlay::new(mask))
+            })
+            .collect();
+        // The indices refer to the old boxes
+        self.selected = None;
+        self.detections = detections;
+    }
+
+    /// Where the zoom mode puts the image inside `bounds`, centered
+    fn zoom_transform(&self, bounds: Size) -> Transform {
+        let width_ratio = bounds.width / self.width as f32;
+        let height_ratio = bounds.height / self.height as f32;
+        let scale = match self.zoom {
+            ZoomMode::Fit => width_ratio.min(height_ratio).min(1.0),
+            ZoomMode::Actual => 1.0,
+            ZoomMode::Fill => width_ratio.max(height_ratio),
+        };
+        let origin = Point::new(
+            (bounds.width - self.width as f32 * scale) / 2.0,
+            (bounds.height - self.height as f32 * scale) / 2.0,
+        );
+        Transform { origin, scale }
+    }
+
+    fn transform(&self, viewport: &Viewport, bounds: Size) -> Transform {
+        match viewport.transform {
+            Some(transform) if viewport.request == self.zoom_request => transform,
+            _ => self.zoom_transform(bounds),
+        }
+    }
+
+    /// Overview of the whole image in the corner, with the visible part outlined.
+    /// Only drawn when part of the image is out of view.
+    fn draw_minimap(
+        &self,
+        frame: &mut canvas::Frame,
+        image: &DisplayImage,
+        transform: Transform,
+        bounds: Size,
+    ) {
+        const SIZE: f32 = 160.0;
+        const MARGIN: f32 = 10.0;
+
+        // The visible part of the canvas in image pixels
+        let visible = Rectangle::new(
+            Point::new(
+                -transform.origin.x / transform.scale,
+                -transform.origin.y / transform.scale,
+            ),
+            Size::new(
+                bounds.width / transform.scale,
+                bounds.height / transform.scale,
+            ),
+        );
+        let image_bounds = Rectangle::new(
+            Point::ORIGIN,
+            Size::new(self.width as f32, self.height as f32),
+        );
+        let Some(visible) = visible.intersection(&image_bounds) else {
+            return;
+        };
+        if visible.width >= image_bounds.width && visible.height >= image_bounds.height {
+            return;
+        }
+
+        let scale = SIZE / self.width.max(self.height) as f32;
+        let size = Size::new(self.width as f32 * scale, self.height as f32 * scale);
+        let position = Point::new(
+            bounds.width - size.width - MARGIN,
+            bounds.height - size.height - MARGIN,
+        );
+        frame.fill_rectangle(
+            position - Vector::new(2.0, 2.0),
+            Size::new(size.width + 4.0, size.height + 4.0),
+            Color::from_rgba(0.0, 0.0, 0.0, 0.6),
+        );
+        frame.draw_image(Rectangle::new(position, size), image.smallest());
+        frame.stroke_rectangle(
+            position + Vector::new(visible.x * scale, visible.y * scale),
+            Size::new(visible.width * scale, visible.height * scale),
+            Stroke::default().with_color(Color::WHITE).with_width(1.5),
+        );
+    }
 }
 
 impl Default for Image {
     fn default() -> Self {
-        let image = iced::widget::image::Handle::from_bytes(DEFAULT_IMAGE.to_vec());
+        let image = DisplayImage::encoded(DEFAULT_IMAGE);
         Self {
+            width: image.width,
+            height: image.height,
             image: Some(image),
-            width: 640,
-            height: 480,
+            annotated: None,
+            show_annotated: false,
+            detections: Detections::default(),
+            overlays: vec![],
+            selected: None,
+            zoom: ZoomMode::Fit,
+            zoom_request: Self::next_zoom_request(),
+            editing: false,
+            classes: ClassFilter::default(),
         }
     }
 }
 
+/// The results of a detection as kept for an image. The annotated image is only kept while the
+/// image is shown, so a batch doesn't hold the pixels of every image.
+#[derive(Debug, Clone)]
+pub struct ImageResults {
+    pub detections: Detections,
+    /// Size of the image the detections are for
+    pub width: u32,
+    pub height: u32,
+    pub annotated: Option<Arc<image::DynamicImage>>,
+}
+
+impl ImageResults {
+    pub fn new(results: &DetectionResults, keep_annotated: bool) -> Self {
+        Self {
+            detections: results.detections.clone(),
+            width: results.annotated.width(),
+            height: results.annotated.height(),
+            annotated: keep_annotated.then(|| results.annotated.clone()),
+        }
+    }
+}
+
+/// The full image of an entry, decoded when it is shown
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    pub image: Arc<image::DynamicImage>,
+    pub display: DisplayImage,
+}
+
+impl DecodedImage {
+    /// Decode and convert for display on a blocking thread
+    pub async fn load(path: PathBuf) -> Result<Self, io::LoadError> {
+        tokio::task::spawn_blocking(move || {
+            let image = io::load_image(&path)?;
+            Ok(Self {
+                display: DisplayImage::new(&image),
+                image: Arc::new(image),
+            })
+        })
+        .await
+        .expect("Failed to decode image")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub handle: iced::advanced::image::Handle,
+    /// Size of the full image
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An image opened by the user, with the results of the last detection on it.
+///
+/// Opening only lists the files. Thumbnails are made afterwards, one at a time, and the full
+/// image is only kept decoded while it is shown.
+#[derive(Debug, Clone)]
+pub struct ImageEntry {
+    pub id: backend::ImageId,
+    pub path: PathBuf,
+    pub decoded: Option<DecodedImage>,
+    pub thumbnail: Option<Thumbnail>,
+    pub results: Option<ImageResults>,
+}
+
+impl ImageEntry {
+    const THUMBNAIL_SIZE: u32 = 96;
+
+    pub fn new(path: PathBuf) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            path,
+            decoded: None,
+            thumbnail: None,
+            results: None,
+        }
+    }
+
+    /// Size of the image, once it has been read
+    pub fn size(&self) -> Option<(u32, u32)> {
+        match (&self.decoded, &self.thumbnail) {
+            (Some(decoded), _) => Some((decoded.display.width, decoded.display.height)),
+            (None, Some(thumbnail)) => Some((thumbnail.width, thumbnail.height)),
+            (None, None) => None,
+        }
+    }
+
+    /// The image for the backend, which decodes it from the file unless it is shown
+    pub fn image_ref(&self) -> backend::ImageRef {
+        backend::ImageRef {
+            id: self.id,
+            path: self.path.clone(),
+            image: self.decoded.as_ref().map(|decoded| decoded.image.clone()),
+        }
+    }
+}
+
+/// Make the thumbnails of the given images in turn, each on a blocking thread, so only one
+/// decoded image is in memory at a time. Images that fail to load have no thumbnail.
+pub fn thumbnails(
+    images: Vec<(backend::ImageId, PathBuf)>,
+) -> impl futures::Stream<Item = (backend::ImageId, Option<Thumbnail>)> {
+    futures::stream::iter(images).then(|(id, path)| async move {
+        let thumbnail = tokio::task::spawn_blocking(move || {
+            let image = io::load_image(&path)
+                .inspect_err(|_| log::warn!("Failed to load image {}", path.display()))
+                .ok()?;
+            let small = image
+                .thumbnail(ImageEntry::THUMBNAIL_SIZE, ImageEntry::THUMBNAIL_SIZE)
+                .to_rgba8();
+            Some(Thumbnail {
+                handle: iced::advanced::image::Handle::from_rgba(
+                    small.width(),
+                    small.height(),
+                    small.into_raw(),
+                ),
+                width: image.width(),
+                height: image.height(),
+            })
+        })
+        .await
+        .ok()
+        .flatten();
+        (id, thumbnail)
+    })
+}
+
+/// Deterministic color for a class name, the same as in the annotated images
+pub fn class_color(class: &str) -> Color {
+    let [r, g, b] = palette::class_rgb(class);
+    Color::from_rgb8(r, g, b)
+}
+
 impl canvas::Program<Message> for Image {
-    type State = ();
+    type State = Viewport;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<Message>) {
+        let canvas::Event::Mouse(event) = event else {
+            return (event::Status::Ignored, None);
+        };
+        // Start from the zoom mode again after a new image or a zoom button
+        if state.request != self.zoom_request {
+            state.transform = None;
+            state.request = self.zoom_request;
+        }
+        let transform = self.transform(state, bounds.size());
+
+        match event {
+            mouse::Event::WheelScrolled { delta } => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                let steps = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / 50.0,
+                };
+                let zoomed = transform.zoom(1.2_f32.powf(steps), position);
+                state.transform = Some(zoomed);
+                state.hovered = self.box_at(zoomed, position);
+                (event::Status::Captured, None)
+            }
+            mouse::Event::ButtonPressed(mouse::Button::Left) if self.editing => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                let drag = self.start_box_drag(transform, position);
+                state.edit = Some(drag);
+                (
+                    event::Status::Captured,
+                    Some(Message::DetectionClicked(drag.index())),
+                )
+            }
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                state.drag = Some(position);
+                state.press = Some(position);
+                (event::Status::Captured, None)
+            }
+            // Panning with the right button works in edit mode too
+            mouse::Event::ButtonPressed(mouse::Button::Right) => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                state.drag = Some(position);
+                (event::Status::Captured, None)
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Right) if state.drag.is_some() => {
+                state.drag = None;
+                (event::Status::Captured, None)
+            }
+            mouse::Event::CursorMoved { .. } => {
+                let Some(position) = cursor.position_from(bounds.position()) else {
+                    return (event::Status::Ignored, None);
+                };
+                let Some(last) = state.drag else {
+                    state.hovered = cursor
+                        .position_in(bounds)
+                        .and_then(|position| self.box_at(transform, position));
+                    // Redraw the box being edited
+                    let status = match state.edit {
+                        Some(_) => event::Status::Captured,
+                        None => event::Status::Ignored,
+                    };
+                    return (status, None);
+                };
+                state.transform = Some(Transform {
+                    origin: transform.origin + (position - last),
+                    ..transform
+                });
+                state.drag = Some(position);
+                (event::Status::Captured, None)
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Left) if state.edit.is_some() => {
+                let edit = state.edit.take();
+                let message = edit.zip(cursor.position_from(bounds.position())).and_then(
+                    |(edit, position)| {
+                        self.finish_box_drag(edit, transform.to_image(position), transform.scale)
+                    },
+                );
+                (event::Status::Captured, message)
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Left) if state.drag.is_some() => {
+                state.drag = None;
+                let press = state.press.take();
+                let position = cursor.position_from(bounds.position());
+                // Barely moving counts as a click rather than a pan
+                let clicked = match (press, position) {
+                    (Some(press), Some(position)) => press.distance(position) < 4.0,
+                    _ => false,
+                };
+                let message = clicked
+                    .then(|| position.map(|position| self.box_at(transform, position)))
+                    .flatten()
+                    .map(Message::DetectionClicked);
+                (event::Status::Captured, message)
+            }
+            mouse::Event::CursorLeft => {
+                state.hovered = None;
+                (event::Status::Ignored, None)
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if state.drag.is_some() {
+            mouse::Interaction::Grabbing
+        } else if self.editing && (state.edit.is_some() || cursor.is_over(bounds)) {
+            let transform = self.transform(state, bounds.size());
+            let on_corner = cursor
+                .position_in(bounds)
+                .and_then(|position| self.corner_at(transform, position))
+                .is_some();
+            match state.edit {
+                Some(BoxDrag::Draw { .. }) => mouse::Interaction::Crosshair,
+                Some(BoxDrag::Move { .. }) => mouse::Interaction::Grabbing,
+                Some(BoxDrag::Resize { .. }) => mouse::Interaction::ResizingDiagonallyDown,
+                None if on_corner => mouse::Interaction::ResizingDiagonallyDown,
+                None if state.hovered.is_some() => mouse::Interaction::Move,
+                None => mouse::Interaction::Crosshair,
+            }
+        } else if state.hovered.is_some() {
+            mouse::Interaction::Pointer
+        } else if cursor.is_over(bounds) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::default()
+        }
+    }
 
     fn draw(
         &self,
-        _state: &Self::State,
+        state: &Self::State,
         renderer: &Renderer,
-        theme: &Theme,
+        _theme: &Theme,
         bounds: Rectangle,
-        _cursor: mouse::Cursor,
+        cursor: mouse::Cursor,
     ) -> Vec<canvas::Geometry> {
-        // log::debug!("Bounds {bounds:?}");
-        // log::debug!("Image size: {}x{}", self.width, self.height);
-
-        let palette = theme.extended_palette();
+        let Some(image) = &self.image else {
+            return vec![];
+        };
+        let transform = self.transform(state, bounds.size());
+        let target = Rectangle::new(
+            transform.origin,
+            Size::new(
+                self.width as f32 * transform.scale,
+                self.height as f32 * transform.scale,
+            ),
+        );
 
         let mut image_frame = canvas::Frame::new(renderer, bounds.size());
-        if let Some(image) = &self.image {
-            let size = iced::Size {
-                width: self.width as f32,
-                height: self.height as f32,
-            };
-            let bounds = Rectangle::with_size(size);
-            image_frame.draw_image(bounds, image);
+        let annotated = self.annotated.as_ref().filter(|_| self.show_annotated);
+        if let Some(annotated) = annotated {
+            // The detections are drawn into it already
+            image_frame.draw_image(target, annotated.level(transform.scale));
+            let mut frame = canvas::Frame::new(renderer, bounds.size());
+            self.draw_minimap(&mut frame, annotated, transform, bounds.size());
+            return vec![image_frame.into_geometry(), frame.into_geometry()];
+        }
+        image_frame.draw_image(target, image.level(transform.scale));
+        // Masks cover the whole image, so they line up with it
+        for (overlay, mask) in self.overlays.iter().zip(&self.detections.masks) {
+            if self.classes.shows(&mask.class) {
+                image_frame.draw_image(target, &overlay.handle);
+            }
         }
 
+        // Detections are in image pixel space, transform them like the image
+        let scale = transform.scale;
+        let to_canvas = |point: [f32; 2]| transform.to_canvas(point);
         let mut frame = canvas::Frame::new(renderer, bounds.size());
 
-        // debugging //
-        // let rect = Rectangle::with_size((480, 640).into());
-        let rect = Path::rectangle((0.0, 0.0).into(), (200.0, 250.0).into());
-        // let color = iced::Color::from_rgb8(255, 0, 0);
-        // let style = iced::widget::canvas::Style::Solid(color);
-        // let fill = iced::widget::canvas::Fill::default();
-        let fill: canvas::Fill = iced::Color::from_rgb8(255, 0, 0).into();
-        frame.fill(&rect, fill);
+        let shown = |class: &String| self.classes.shows(class);
+        for polygon in self
+            .detections
+            .polygons
+            .iter()
+            .filter(|polygon| shown(&polygon.class))
+        {
+            let path = Path::new(|builder| {
+                for (i, point) in polygon.points.iter().enumerate() {
+                    if i == 0 {
+                        builder.move_to(to_canvas(*point));
+                    } else {
+                        builder.line_to(to_canvas(*point));
+                    }
+                }
+                builder.close();
+            });
+            let stroke = Stroke::default().with_color(class_color(&polygon.class));
+            frame.stroke(&path, stroke.with_width(1.5));
+        }
 
-        frame.fill_rectangle(
-            Point::ORIGIN,
-            bounds.size(),
-            // palette.background.strong.color,
-            iced::Color::from_rgba(0.5, 0.5, 0.5, 0.5),
-        );
+        for rotated in self
+            .detections
+            .rotated_boxes
+            .iter()
+            .filter(|rotated| shown(&rotated.class))
+        {
+            let path = Path::new(|builder| {
+                builder.move_to(to_canvas(rotated.vertices[0]));
+                for vertex in &rotated.vertices[1..] {
+                    builder.line_to(to_canvas(*vertex));
+                }
+                builder.close();
+            });
+            let stroke = Stroke::default().with_color(class_color(&rotated.class));
+            frame.stroke(&path, stroke.with_width(2.0));
+        }
 
-        frame.stroke_rectangle(
-            Point::ORIGIN,
-            (10.0, 10.0).into(),
-            Stroke {
-                ..Stroke::default()
-            },
-        );
+        for (i, keypoints) in self.detections.keypoints.iter().enumerate() {
+            // Pose models give the keypoints in the same order as the boxes
+            if self
+                .detections
+                .boxes
+                .get(i)
+                .is_some_and(|bbox| !shown(&bbox.class))
+            {
+                continue;
+            }
+            let placed = |i: usize| {
+                keypoints
+                    .get(i)
+                    .filter(|keypoint| keypoint.confidence > 0.0)
+            };
+            if keypoints.len() == 17 {
+                for (a, b) in COCO_SKELETON {
+                    if let (Some(a), Some(b)) = (placed(a), placed(b)) {
+                        frame.stroke(
+                            &Path::line(to_canvas([a.x, a.y]), to_canvas([b.x, b.y])),
+                            Stroke::default()
+                                .with_color(color!(0x46f0f0))
+                                .with_width(2.0),
+                        );
+                    }
+                }
+            }
+            for keypoint in (0..keypoints.len()).filter_map(placed) {
+                frame.fill(
+                    &Path::circle(to_canvas([keypoint.x, keypoint.y]), 3.0),
+                    color!(0xffe119),
+                );
+            }
+        }
 
-        let center = frame.center();
-        frame.translate(Vector::new(center.x, center.y));
+        // The box being edited follows the cursor until the button is released
+        let preview =
+            state
+                .edit
+                .zip(cursor.position_from(bounds.position()))
+                .map(|(edit, position)| {
+                    (
+                        edit.index(),
+                        self.drag_rect(&edit, transform.to_image(position)),
+                    )
+                });
 
-        let r = Rectangle::with_radius(72.0);
-        frame.fill_rectangle(
-            (r.x, r.y).into(),
-            r.size(),
-            iced::Color::from_rgba(0.5, 0.5, 1.0, 0.5),
-        );
+        for (i, bbox) in self.detections.boxes.iter().enumerate() {
+            if !shown(&bbox.class) {
+                continue;
+            }
+            let color = class_color(&bbox.class);
+            let rect = match preview {
+                Some((Some(index), rect)) if index == i => rect,
+                _ => box_rect(bbox),
+            };
+            let top_left = to_canvas([rect.x, rect.y]);
+            let size = Size::new(rect.width * scale, rect.height * scale);
+            let highlight = if self.selected == Some(i) {
+                Some(0.35)
+            } else if state.hovered == Some(i) {
+                Some(0.2)
+            } else {
+                None
+            };
+            if let Some(alpha) = highlight {
+                frame.fill_rectangle(top_left, size, Color { a: alpha, ..color });
+            }
+            frame.stroke_rectangle(
+                top_left,
+                size,
+                Stroke::default()
+                    .with_color(color)
+                    .with_width(if highlight.is_some() { 4.0 } else { 2.0 }),
+            );
 
-        // let im = iced::widget::image::Handle::from_bytes(DEFAULT_IMAGE.to_vec());
-        // frame.draw_image(Rectangle::with_radius(480.0), &im);
+            let caption = format!("{} {:.2}", bbox.class, bbox.confidence);
+            let text_size = 14.0;
+            let label_height = text_size + 4.0;
+            // Approximate the label width, the canvas cannot measure text
+            let label_width = caption.chars().count() as f32 * text_size * 0.6 + 4.0;
+            // Put the label above the box, or inside it if there's no room
+            let label_y = if top_left.y - label_height >= target.y.max(0.0) {
+                top_left.y - label_height
+            } else {
+                top_left.y
+            };
+            frame.fill_rectangle(
+                Point::new(top_left.x, label_y),
+                Size::new(label_width, label_height),
+                color,
+            );
+            frame.fill_text(canvas::Text {
+                content: caption,
+                position: Point::new(top_left.x + 2.0, label_y + 2.0),
+                color: Color::BLACK,
+                size: text_size.into(),
+                ..canvas::Text::default()
+            });
 
-        vec![frame.into_geometry(), image_frame.into_geometry()]
+            if self.editing && self.selected == Some(i) {
+                for corner in corners(rect) {
+                    let corner = to_canvas([corner.x, corner.y]);
+                    frame.fill_rectangle(
+                        corner - Vector::new(4.0, 4.0),
+                        Size::new(8.0, 8.0),
+                        Color::WHITE,
+                    );
+                    frame.stroke_rectangle(
+                        corner - Vector::new(4.0, 4.0),
+                        Size::new(8.0, 8.0),
+                        Stroke::default().with_color(color),
+                    );
+                }
+            }
+        }
+
+        if let Some((None, rect)) = preview {
+            frame.stroke_rectangle(
+                to_canvas([rect.x, rect.y]),
+                Size::new(rect.width * scale, rect.height * scale),
+                Stroke {
+                    line_dash: canvas::LineDash {
+                        segments: &[6.0, 4.0],
+                        offset: 0,
+                    },
+                    ..Stroke::default().with_color(Color::WHITE).with_width(2.0)
+                },
+            );
+        }
+
+        self.draw_minimap(&mut frame, image, transform, bounds.size());
+
+        let hovered = state
+            .hovered
+            .and_then(|index| self.detections.boxes.get(index));
+        if let (Some(bbox), Some(position), None, None) =
+            (hovered, cursor.position_in(bounds), state.drag, state.edit)
+        {
+            self.draw_tooltip(&mut frame, bbox, position);
+        }
+
+        vec![image_frame.into_geometry(), frame.into_geometry()]
     }
 }
 
-pub fn view(app: &ZeroShotRust) -> Element<Message> {
-    // TODO: app.image and app.inference_state.image should always be the same image (Arc<DynamicImage>)
-    // so no reason to use an if/else here
-    let image: Element<Message> = if let Some(image) = &app.image {
-        // Convert DynamicImage to iced image
-        let rgba = image.to_rgba8();
-        let im = iced::advanced::image::Handle::from_rgba(
-            rgba.width(),
-            rgba.height(),
-            rgba.as_raw().to_vec(),
-        );
-        iced::widget::image(im)
-            .opacity(0.5)
-            .content_fit(iced::ContentFit::ScaleDown)
+/// Column to sort the detection table by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Class,
+    Confidence,
+    Area,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectionSort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl Default for DetectionSort {
+    fn default() -> Self {
+        Self {
+            key: SortKey::Confidence,
+            descending: true,
+        }
+    }
+}
+
+/// Height of a row in the detection table, fixed so we can scroll to a row
+const TABLE_ROW_HEIGHT: f32 = 28.0;
+
+pub fn detection_table_id() -> scrollable::Id {
+    scrollable::Id::new("detection-table")
+}
+
+/// Scroll the detection table so that the box at `index` is at the top
+pub fn scroll_to_detection(state: &InferenceState, index: usize) -> Task<Message> {
+    let Some(row) = state
+        .sorted_detections()
+        .iter()
+        .position(|sorted| *sorted == index)
+    else {
+        return Task::none();
+    };
+    scrollable::scroll_to(
+        detection_table_id(),
+        scrollable::AbsoluteOffset {
+            x: 0.0,
+            y: row as f32 * TABLE_ROW_HEIGHT,
+        },
+    )
+}
+
+/// The shown boxes, sortable by clicking the headers. Clicking a row selects the box.
+fn detection_table(state: &InferenceState) -> Element<Message> {
+    let header = |label: &str, key: SortKey| {
+        let arrow = match (state.sort.key == key, state.sort.descending) {
+            (true, true) => " ▼",
+            (true, false) => " ▲",
+            (false, _) => "",
+        };
+        button(text(format!("{label}{arrow}")))
+            .style(button::text)
+            .width(Fill)
+            .on_press(Message::SortDetections(key))
+    };
+    let headers = row![
+        header("Class", SortKey::Class),
+        header("Confidence", SortKey::Confidence),
+        header("Area", SortKey::Area),
+    ];
+
+    let boxes = state.image.boxes();
+    let selected = state.image.selected();
+    let sorted = state.sorted_detections();
+    let count = sorted.len();
+    let rows = sorted.into_iter().map(|index| {
+        let bbox = &boxes[index];
+        let cells = row![
+            text(bbox.class.clone()).width(Fill),
+            text(format!("{:.2}", bbox.confidence)).width(Fill),
+            text(format!("{:.0}", bbox.width * bbox.height)).width(Fill),
+        ];
+        let style = if selected == Some(index) {
+            button::primary
+        } else {
+            button::text
+        };
+        button(cells)
+            .style(style)
+            .width(Fill)
+            .height(TABLE_ROW_HEIGHT)
+            .padding([4, 8])
+            .on_press(Message::SelectDetection(index))
             .into()
+    });
+
+    let edited = if state.annotations().is_some() {
+        ", edited"
     } else {
-        // let im = iced::widget::image::Handle::from_bytes(DEFAULT_IMAGE.to_vec());
-        // iced::widget::image(im)
-        // square(640, 480)
+        ""
+    };
+    column![
+        text(format!("Detections ({count}{edited})")),
+        headers,
+        scrollable(column(rows))
+            .id(detection_table_id())
+            .height(Fill),
+    ]
+    .spacing(5)
+    .padding(10)
+    .width(400)
+    .into()
+}
+
+/// Classes in the shown detections with their colors and counts, to hide them or show one alone
+fn legend(state: &InferenceState) -> Element<Message> {
+    let counts = state.image.detections().class_counts();
+    if counts.is_empty() {
+        return Space::new(0, 0).into();
+    }
 
-        canvas(&app.inference_state.image)
-            .width(640)
-            .height(480)
+    let filter = &state.class_filter;
+    let rows = counts.into_iter().map(|(class, count)| {
+        let color = class_color(&class);
+        let swatch = container(Space::new(14, 14))
+            .style(move |_theme: &Theme| container::Style::from(color));
+        let only = filter.only.as_ref() == Some(&class);
+        let visible = checkbox(class.clone(), filter.shows(&class)).on_toggle_maybe(
+            filter.only.is_none().then(|| {
+                let class = class.clone();
+                move |shown| Message::ToggleClass(class.clone(), shown)
+            }),
+        );
+        let show_only = button(text("Only").size(12))
+            .style(if only { button::primary } else { button::text })
+            .on_press(Message::ShowOnlyClass((!only).then(|| class.clone())));
+        row![swatch, visible, horizontal_space(), text(count), show_only]
+            .spacing(8)
+            .align_y(Center)
             .into()
+    });
+
+    column![
+        text("Classes"),
+        container(scrollable(column(rows).spacing(2))).max_height(200),
+    ]
+    .spacing(5)
+    .padding(10)
+    .width(400)
+    .into()
+}
+
+/// Switch for editing the boxes by hand, with the actions on the selected box and the edits
+fn edit_toolbar(state: &InferenceState) -> Element<Message> {
+    let toggle = checkbox("Edit boxes", state.editing).on_toggle(Message::ToggleEditing);
+    let annotations = state.annotations();
+    if !state.editing {
+        // Edited boxes are shown instead of the model's, also over newer results
+        let content: Element<Message> = match annotations {
+            Some(_) => row![
+                toggle,
+                text("Showing boxes edited by hand").size(12),
+                button("Discard edits")
+                    .style(button::secondary)
+                    .on_press(Message::DiscardEdits),
+            ]
+            .spacing(5)
+            .align_y(Center)
+            .into(),
+            None => toggle.into(),
+        };
+        return container(content).padding(10).width(400).into();
+    }
+
+    let selected = state
+        .image
+        .selected()
+        .and_then(|index| state.image.boxes().get(index));
+    let relabel = pick_list(
+        state.export_class_names(),
+        selected.map(|bbox| bbox.class.clone()),
+        Message::RelabelSelected,
+    )
+    .placeholder("Class");
+    let actions = row![
+        relabel,
+        button("Delete")
+            .style(button::danger)
+            .on_press_maybe(selected.map(|_| Message::DeleteSelected)),
+        button("Undo").on_press_maybe(
+            annotations
+                .filter(|annotations| annotations.can_undo())
+                .map(|_| Message::Undo)
+        ),
+        button("Redo").on_press_maybe(
+            annotations
+                .filter(|annotations| annotations.can_redo())
+                .map(|_| Message::Redo)
+        ),
+        button("Discard edits")
+            .style(button::secondary)
+            .on_press_maybe(annotations.map(|_| Message::DiscardEdits)),
+    ]
+    .spacing(5)
+    .align_y(Center);
+
+    column![
+        toggle,
+        actions,
+        text(
+            "Drag to draw a box, drag a box or its corners to move or resize it, right-drag to pan"
+        )
+        .size(12),
+    ]
+    .spacing(5)
+    .padding(10)
+    .width(400)
+    .into()
+}
+
+/// Panel for editing the open-vocabulary text prompts (class names)
+fn prompts(state: &InferenceState) -> Element<Message> {
+    let count = state.params.class_names.len();
+    let rows = state
+        .params
+        .class_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let up = button("Up").on_press_maybe((i > 0).then_some(Message::MovePromptUp(i)));
+            let down = button("Down")
+                .on_press_maybe((i + 1 < count).then_some(Message::MovePromptDown(i)));
+            let remove = button("Remove")
+                .style(button::danger)
+                .on_press(Message::RemovePrompt(i));
+            row![
+                text_input("Prompt", name)
+                    .on_input(move |prompt| Message::EditPrompt(i, prompt))
+                    .on_submit(Message::SubmitParams),
+                up,
+                down,
+                remove,
+            ]
+            .spacing(5)
+            .into()
+        });
+
+    column![
+        text("Prompts"),
+        scrollable(column(rows).spacing(5)).height(Fill),
+        button("Add prompt").on_press(Message::AddPrompt),
+    ]
+    .spacing(10)
+    .padding(10)
+    .width(400)
+    .into()
+}
+
+fn error_banner(error: Option<&backend::BackendError>) -> Element<Message> {
+    match error {
+        Some(error) => banner(error.to_string(), Message::DismissError, |palette| {
+            palette.danger.weak
+        }),
+        None => Space::new(0, 0).into(),
+    }
+}
+
+/// Tells what happened after the backend stopped, in the error colors once it is given up on
+fn notice_banner(notice: Option<&BackendNotice>) -> Element<'_, Message> {
+    match notice {
+        Some(BackendNotice::Stopped) => banner(
+            BackendNotice::Stopped.to_string(),
+            Message::DismissNotice,
+            |palette| palette.danger.weak,
+        ),
+        Some(notice) => banner(notice.to_string(), Message::DismissNotice, |palette| {
+            palette.primary.weak
+        }),
+        None => Space::new(0, 0).into(),
+    }
+}
+
+/// A message across the window with a button to dismiss it
+fn banner<'a>(
+    message: String,
+    dismiss: Message,
+    colors: fn(&iced::theme::palette::Extended) -> iced::theme::palette::Pair,
+) -> Element<'a, Message> {
+    let banner = row![
+        text(message),
+        horizontal_space(),
+        button("Dismiss").style(button::secondary).on_press(dismiss),
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    container(banner)
+        .padding(10)
+        .width(Fill)
+        .style(move |theme: &Theme| {
+            let colors = colors(theme.extended_palette());
+            container::Style::from(colors.color).color(colors.text)
+        })
+        .into()
+}
+
+/// Sliders for the class and text confidence thresholds, the latter only for prompted models
+fn thresholds(state: &InferenceState) -> Element<Message> {
+    let class_confidence = state.params.class_confidence;
+    let text_confidence = state.params.text_confidence;
+
+    let mut sliders = column![
+        text(format!("Class confidence: {class_confidence:.2}")),
+        slider(
+            backend::DetectionParams::CONFIDENCE_FLOOR..=1.0,
+            class_confidence,
+            Message::SetClassConfidence
+        )
+        .step(0.01)
+        .on_release(Message::ThresholdsReleased),
+    ];
+    if state.capabilities().open_vocabulary {
+        sliders = sliders.push(text(format!("Text confidence: {text_confidence:.2}")));
+        sliders = sliders.push(
+            slider(0.0..=1.0, text_confidence, Message::SetTextConfidence)
+                .step(0.01)
+                .on_release(Message::ThresholdsReleased),
+        );
+    }
+
+    sliders.spacing(5).padding(10).width(400).into()
+}
+
+/// Text typed into the post-processing fields, kept as typed so half-written numbers aren't lost
+#[derive(Debug, Clone, Default)]
+pub struct PostProcessingForm {
+    pub min_area: String,
+    pub max_area: String,
+    pub top_k: String,
+    pub synonyms: String,
+}
+
+/// Options for cleaning up the detections, done by the backend so they apply from the next
+/// detection
+fn post_processing(state: &InferenceState) -> Element<Message> {
+    let options = &state.params.post_processing;
+    let form = &state.post_processing_form;
+
+    let mut panel = column![
+        text("Post-processing, applied on the next detection"),
+        pick_list(Nms::ALL, Some(options.nms), Message::SetNms),
+    ];
+    if options.nms != Nms::Off {
+        panel = panel.push(text(format!("IoU threshold: {:.2}", options.iou_threshold)));
+        panel = panel.push(
+            slider(0.0..=1.0, options.iou_threshold, Message::SetIouThreshold)
+                .step(0.01)
+                .on_release(Message::ThresholdsReleased),
+        );
+    }
+    panel = panel.push(
+        row![
+            text_input("Min area", &form.min_area)
+                .on_input(Message::SetMinArea)
+                .on_submit(Message::SubmitParams),
+            text_input("Max area", &form.max_area)
+                .on_input(Message::SetMaxArea)
+                .on_submit(Message::SubmitParams),
+            text_input("Top-K per class", &form.top_k)
+                .on_input(Message::SetTopK)
+                .on_submit(Message::SubmitParams),
+        ]
+        .spacing(5),
+    );
+    panel = panel.push(
+        text_input("Synonyms: car = automobile, auto; …", &form.synonyms)
+            .on_input(Message::SetSynonyms)
+            .on_submit(Message::SubmitParams),
+    );
+
+    panel.spacing(5).padding(10).width(400).into()
+}
+
+/// Arc going round while a model loads
+struct Spinner {
+    started: Instant,
+}
+
+impl canvas::Program<Message> for Spinner {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        let start_angle = self.started.elapsed().as_secs_f32() * std::f32::consts::TAU;
+        let arc = Path::new(|builder| {
+            builder.arc(canvas::path::Arc {
+                center: frame.center(),
+                radius: bounds.width.min(bounds.height) / 2.0 - 4.0,
+                start_angle: Radians(start_angle),
+                end_angle: Radians(start_angle + std::f32::consts::PI * 1.5),
+            });
+        });
+        frame.stroke(
+            &arc,
+            Stroke::default()
+                .with_color(theme.palette().primary)
+                .with_width(4.0),
+        );
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Covers the screen while model weights load, so nothing looks frozen
+fn loading_overlay(model: &backend::ModelType, started: Instant) -> Element<Message> {
+    let card = container(
+        column![
+            canvas(Spinner { started }).width(48).height(48),
+            text(format!("Loading {model}…")),
+        ]
+        .spacing(10)
+        .align_x(Center),
+    )
+    .padding(20)
+    .style(container::rounded_box);
+
+    opaque(
+        center(card)
+            .style(|_theme: &Theme| container::Style::from(Color::from_rgba(0.0, 0.0, 0.0, 0.5))),
+    )
+}
+
+/// Timings of the last detection and the average of recent ones with the same model and device
+fn hud(state: &InferenceState) -> Element<Message> {
+    let Some(last) = state.last_timings else {
+        return Space::new(0, 0).into();
+    };
+    let history = state
+        .selected_model
+        .clone()
+        .and_then(|model| state.timing_history.get(&(model, state.device())));
+    let average = history.map(|history| Timings::mean(history.iter()));
+
+    let title = match &state.selected_model {
+        Some(model) => format!("{model} on {}", state.device()),
+        None => "Last detection".to_string(),
+    };
+    let mut lines = vec![
+        title,
+        format!(
+            "{:<12}{:>10}{:>10}",
+            "",
+            "last",
+            format!("avg/{}", history.map_or(0, VecDeque::len))
+        ),
+    ];
+    for (i, (stage, duration)) in last.stages().into_iter().enumerate() {
+        let average = average.map(|average| average.stages()[i].1);
+        lines.push(format!(
+            "{stage:<12}{:>10}{:>10}",
+            format_duration(duration),
+            average.map(format_duration).unwrap_or_default()
+        ));
+    }
+
+    container(text(lines.join("\n")).font(Font::MONOSPACE).size(12))
+        .padding(8)
+        .style(|_theme: &Theme| {
+            container::Style::from(Color::from_rgba(0.0, 0.0, 0.0, 0.7)).color(Color::WHITE)
+        })
+        .into()
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Selected model and the execution provider it actually runs on
+fn status_bar(app: &ZeroShotRust) -> Element<Message> {
+    let state = &app.inference_state;
+    let model = match &state.selected_model {
+        Some(model) => model.to_string(),
+        None => "No model selected".to_string(),
+    };
+    let provider = match state.active_device {
+        Some(device) if device != state.params.device => {
+            format!("{device} (requested {})", state.params.device)
+        }
+        Some(device) => device.to_string(),
+        None => format!("{} (not loaded yet)", state.params.device),
+    };
+
+    let load_time = match state.load_ms {
+        Some(load_ms) => format!(" · Loaded in {load_ms} ms"),
+        None => String::new(),
     };
+
+    container(text(format!("{model} · Provider: {provider}{load_time}")).size(14))
+        .padding([2, 10])
+        .width(Fill)
+        .into()
+}
+
+/// Progress of the running detection job, with a button to cancel it
+fn progress(state: &InferenceState) -> Element<Message> {
+    if !state.busy {
+        return Space::new(0, 0).into();
+    }
+
+    let (done, total, label) = match state.progress {
+        Some(progress) => (
+            progress.done,
+            progress.total,
+            format!(
+                "{}/{} images, about {}s left",
+                progress.done,
+                progress.total,
+                progress.eta.as_secs()
+            ),
+        ),
+        None => (0, 1, "Starting…".to_string()),
+    };
+
+    row![
+        progress_bar(0.0..=total as f32, done as f32).height(20),
+        text(label),
+        button("Cancel")
+            .style(button::danger)
+            .on_press(Message::CancelDetection),
+    ]
+    .spacing(10)
+    .padding(10)
+    .align_y(Center)
+    .into()
+}
+
+/// Scrollable strip of thumbnails for the opened images
+fn filmstrip(app: &ZeroShotRust) -> Element<Message> {
+    if app.images.len() < 2 {
+        return Space::new(0, 0).into();
+    }
+
+    let thumbnails = app.images.iter().enumerate().map(|(i, entry)| {
+        let style = if app.current_image == Some(i) {
+            button::primary
+        } else {
+            button::text
+        };
+        let thumbnail: Element<Message> = match &entry.thumbnail {
+            Some(thumbnail) => iced::widget::image(thumbnail.handle.clone())
+                .height(ImageEntry::THUMBNAIL_SIZE as u16)
+                .into(),
+            // Not made yet, or the image failed to load
+            None => center(
+                text(
+                    entry
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+                .size(12),
+            )
+            .width(ImageEntry::THUMBNAIL_SIZE as u16)
+            .height(ImageEntry::THUMBNAIL_SIZE as u16)
+            .into(),
+        };
+        button(thumbnail)
+            .style(style)
+            .padding(4)
+            .on_press(Message::SelectImage(i))
+            .into()
+    });
+
+    let strip = scrollable(row(thumbnails).spacing(5).padding(5))
+        .direction(scrollable::Direction::Horizontal(
+            scrollable::Scrollbar::default(),
+        ))
+        .width(Fill);
+    if app.thumbnails_left == 0 {
+        return strip.into();
+    }
+    let done = app.images.len() - app.thumbnails_left;
+    column![
+        text(format!("Loading thumbnails {done}/{}", app.images.len())).size(12),
+        strip
+    ]
+    .padding([0, 5])
+    .into()
+}
+
+pub fn view(app: &ZeroShotRust) -> Element<Message> {
+    let image: Element<Message> = canvas(&app.inference_state.image)
+        .width(Fill)
+        .height(Fill)
+        .into();
     let image = container(image.explain(iced::Color::from_rgb(1.0, 0.0, 0.0)))
         .padding(10)
         // .width(640)
@@ -238,20 +1830,29 @@ pub fn view(app: &ZeroShotRust) -> Element<Message> {
         // .height(Length::Fixed(480.0))
         .align_y(iced::alignment::Vertical::Center)
         .align_x(iced::alignment::Horizontal::Center);
+    let image: Element<Message> = if app.inference_state.show_hud {
+        stack![image, container(hud(&app.inference_state)).padding(20)].into()
+    } else {
+        image.into()
+    };
 
-    let mut load_image_button = button("Load Image");
+    let mut load_image_button = button("Load Images");
+    let mut load_folder_button = button("Load Folder");
     if !app.inference_state.selecting_image {
         load_image_button = load_image_button.on_press(Message::LoadImage);
+        load_folder_button = load_folder_button.on_press(Message::LoadFolder);
     };
 
     let mut detect_button = button("Run detection");
-    if let Some(image) = &app.image {
-        if app.backend_tx.is_some()
-            && app.inference_state.selected_model.is_some()
-            && !app.inference_state.busy
-        {
-            detect_button = detect_button.on_press(Message::Detect(image.clone()));
-        };
+    let mut detect_all_button = button("Run on all images");
+    if app.current_image().is_some()
+        && app.backend_tx.is_some()
+        && app.inference_state.selected_model.is_some()
+        && !app.inference_state.busy
+        && app.inference_state.loading_model.is_none()
+    {
+        detect_button = detect_button.on_press(Message::Detect);
+        detect_all_button = detect_all_button.on_press(Message::DetectAll);
     };
 
     log::debug!(
@@ -261,48 +1862,452 @@ pub fn view(app: &ZeroShotRust) -> Element<Message> {
         app.inference_state.busy
     );
 
-    let models = vec![backend::ModelType::Mock, backend::ModelType::GroundingDINO];
+    let selected_model = app
+        .inference_state
+        .selected_model
+        .as_ref()
+        .and_then(|model| {
+            app.inference_state
+                .models
+                .iter()
+                .find(|choice| choice.model == *model)
+                .cloned()
+        });
+    let browse_weights_button = button("Browse weights…").on_press_maybe(
+        selected_model
+            .as_ref()
+            .filter(|choice| choice.model.needs_weights())
+            .map(|_| Message::BrowseWeights),
+    );
+    let dtype = selected_model
+        .as_ref()
+        .filter(|choice| choice.model.needs_weights())
+        .and_then(|choice| app.registry.get(&choice.model))
+        .map(|config| config.dtype);
     let model_list = pick_list(
-        models,
-        app.inference_state.selected_model.clone(),
-        Message::SelectModel,
+        app.inference_state.models.as_slice(),
+        selected_model,
+        |choice| Message::SelectModel(choice.model),
     )
     .placeholder("Select a model");
+    let dtype_list: Element<Message> = match dtype {
+        Some(dtype) => pick_list(DType::ALL, Some(dtype), Message::SelectDType).into(),
+        None => Space::new(0, 0).into(),
+    };
+    let device_list = pick_list(
+        backend::Device::available(),
+        Some(app.inference_state.params.device),
+        Message::SelectDevice,
+    );
+    let lazy_loading = checkbox("Load on first detection", app.inference_state.lazy_loading)
+        .on_toggle(Message::ToggleLazyLoading);
+    let model_list = row![
+        model_list,
+        dtype_list,
+        device_list,
+        browse_weights_button,
+        lazy_loading
+    ]
+    .spacing(10)
+    .align_y(Center);
 
-    let menu = row![load_image_button, detect_button]
-        .spacing(20)
-        .align_y(iced::alignment::Vertical::Bottom);
+    let save_detections_button = button("Save detections…").on_press_maybe(
+        (app.inference_state.results.is_some() && app.current_image().is_some())
+            .then_some(Message::SaveDetections),
+    );
+
+    let save_all_button = button("Save all detections…").on_press_maybe(
+        app.images
+            .iter()
+            .any(|entry| entry.results.is_some())
+            .then_some(Message::SaveAllDetections),
+    );
+
+    let save_annotated_button = button("Save annotated image…").on_press_maybe(
+        app.inference_state
+            .results
+            .as_ref()
+            .is_some_and(|results| results.annotated.is_some())
+            .then_some(Message::SaveAnnotated),
+    );
+
+    let autosave_label = match &app.inference_state.autosave_dir {
+        Some(folder) => format!("Auto-save to {}", folder.display()),
+        None => "Auto-save results".to_string(),
+    };
+    let autosave = checkbox(autosave_label, app.inference_state.autosave_dir.is_some())
+        .on_toggle(Message::ToggleAutosave);
+    let zoom = row![
+        button("Fit").on_press(Message::Zoom(ZoomMode::Fit)),
+        button("100%").on_press(Message::Zoom(ZoomMode::Actual)),
+        button("Fill").on_press(Message::Zoom(ZoomMode::Fill)),
+    ]
+    .spacing(5);
+    let show_hud =
+        checkbox("Performance HUD", app.inference_state.show_hud).on_toggle(Message::ToggleHud);
+    let show_annotated = checkbox("Model rendering", app.inference_state.show_annotated)
+        .on_toggle(Message::ToggleAnnotated);
+
+    let menu = row![
+        load_image_button,
+        load_folder_button,
+        detect_button,
+        detect_all_button,
+        save_detections_button,
+        save_all_button,
+        save_annotated_button,
+        autosave,
+        zoom,
+        show_hud,
+        show_annotated,
+    ]
+    .spacing(20)
+    .align_y(iced::alignment::Vertical::Bottom);
     let menu = container(menu).height(50);
 
+    // Closed-set models ignore the prompts, so don't offer to edit them
+    let sidebar = if app.inference_state.capabilities().open_vocabulary {
+        column![
+            prompts(&app.inference_state),
+            thresholds(&app.inference_state),
+            post_processing(&app.inference_state),
+            edit_toolbar(&app.inference_state),
+            legend(&app.inference_state),
+            detection_table(&app.inference_state)
+        ]
+    } else {
+        column![
+            thresholds(&app.inference_state),
+            post_processing(&app.inference_state),
+            edit_toolbar(&app.inference_state),
+            legend(&app.inference_state),
+            detection_table(&app.inference_state)
+        ]
+    };
     let content = column![
-        Space::new(Length::Fill, Length::Fill),
-        image,
+        notice_banner(app.inference_state.notice.as_ref()),
+        error_banner(app.inference_state.error.as_ref()),
+        row![image, sidebar],
+        filmstrip(app),
+        progress(&app.inference_state),
         menu,
         model_list,
-        Space::new(Length::Fill, Length::Fill),
+        status_bar(app)
     ]
     .align_x(iced::alignment::Horizontal::Center);
 
-    center(content).into()
+    match &app.inference_state.loading_model {
+        Some((model, started)) => stack![center(content), loading_overlay(model, *started)].into(),
+        None => center(content).into(),
+    }
+}
+
+/// A detection sent to the backend. Results for any other request are outdated and dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Request {
+    pub id: backend::RequestId,
+    /// Whether it covers all the opened images rather than the one shown
+    pub batch: bool,
+}
+
+impl Request {
+    pub fn new(batch: bool) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            batch,
+        }
+    }
+}
+
+/// Entry in the model picker, along with anything preventing the model from loading
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelChoice {
+    pub model: backend::ModelType,
+    pub problem: Option<String>,
+}
+
+impl std::fmt::Display for ModelChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.problem {
+            Some(problem) => write!(f, "{} ({problem})", self.model),
+            None => write!(f, "{}", self.model),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct InferenceState {
     pub selecting_image: bool,
     pub selected_model: Option<backend::ModelType>,
+    pub models: Vec<ModelChoice>,
     pub busy: bool,
-    // pub detections: Vec<backend::Detection>,
+    /// The running detection
+    pub request: Option<Request>,
+    /// Progress of the running detection job, if it has processed any images yet
+    pub progress: Option<backend::Progress>,
+    pub results: Option<ImageResults>,
+    pub params: backend::DetectionParams,
+    pub error: Option<backend::BackendError>,
+    /// Shown after the backend stopped unexpectedly
+    pub notice: Option<BackendNotice>,
+    /// Where the model actually runs, reported by the backend after loading it
+    pub active_device: Option<backend::Device>,
+    /// The model whose weights are being loaded, and since when
+    pub loading_model: Option<(backend::ModelType, Instant)>,
+    /// How long loading the current model took
+    pub load_ms: Option<u64>,
+    pub lazy_loading: bool,
+    pub show_hud: bool,
+    /// Show usls' annotated image instead of drawing the detections
+    pub show_annotated: bool,
+    pub sort: DetectionSort,
+    pub last_timings: Option<Timings>,
+    /// Recent timings for each model and device, for the averages in the HUD
+    pub timing_history: HashMap<(backend::ModelType, backend::Device), VecDeque<Timings>>,
+    /// Folder to save every annotated result to, if enabled
+    pub autosave_dir: Option<PathBuf>,
+    /// Boxes corrected by hand for each image, shown and exported instead of the model's
+    pub edits: HashMap<backend::ImageId, Annotations>,
+    /// The image being shown, to look up its edits
+    pub image_id: Option<backend::ImageId>,
+    pub editing: bool,
+    pub class_filter: ClassFilter,
+    pub post_processing_form: PostProcessingForm,
+    /// Prompts or post-processing typed but not sent to the backend yet, they are sent on Enter
+    /// or before the next detection
+    pub params_edited: bool,
     // pub image: Option<iced::advanced::image::Handle>,
     pub image: Image,
 }
 
+impl InferenceState {
+    /// Store the raw results from the backend, and show the ones passing the current thresholds
+    pub fn set_results(&mut self, results: ImageResults) {
+        self.results = Some(results);
+        self.refilter();
+    }
+
+    /// Re-apply the confidence threshold to the last results without re-running detection.
+    ///
+    /// The text confidence only affects how the model grounds prompts to boxes, so it
+    /// can not be applied here and takes effect on the next detection.
+    pub fn refilter(&mut self) {
+        self.image.set_detections(self.detections());
+    }
+
+    /// The detections passing the current thresholds, with the boxes edited by hand if there are
+    /// edits for the image. Edited boxes are kept whatever their confidence, along with the
+    /// polygons, masks and keypoints of the model's boxes they started from.
+    pub fn detections(&self) -> Detections {
+        self.detections_of(self.results.as_ref(), self.annotations())
+    }
+
+    /// The detections of any image with results, to export them, see [`Self::detections`]
+    pub fn image_detections(&self, entry: &ImageEntry) -> Option<Detections> {
+        let results = entry.results.as_ref()?;
+        Some(self.detections_of(Some(results), self.edits.get(&entry.id)))
+    }
+
+    fn detections_of(
+        &self,
+        results: Option<&ImageResults>,
+        annotations: Option<&Annotations>,
+    ) -> Detections {
+        let raw = results.map(|results| &results.detections);
+        let mut detections = raw
+            .map(|raw| raw.filter_confidence(self.params.class_confidence))
+            .unwrap_or_default();
+        if let Some(annotations) = annotations {
+            detections.boxes = annotations.boxes().to_vec();
+            if let Some(raw) = raw {
+                if let Some(polygons) = annotations.aligned(&raw.polygons, raw) {
+                    detections.polygons = polygons;
+                }
+                if let Some(masks) = annotations.aligned(&raw.masks, raw) {
+                    detections.masks = masks;
+                }
+                if let Some(keypoints) = annotations.aligned(&raw.keypoints, raw) {
+                    detections.keypoints = keypoints;
+                }
+            }
+        }
+        detections
+    }
+
+    /// Manual edits to the boxes of the current image
+    pub fn annotations(&self) -> Option<&Annotations> {
+        self.image_id.and_then(|id| self.edits.get(&id))
+    }
+
+    /// Change the boxes of the current image by hand, starting from the boxes shown on the first
+    /// edit. Selects the changed box.
+    pub fn edit(&mut self, edit: BoxEdit) {
+        let Some(id) = self.image_id else {
+            return;
+        };
+        if !self.edits.contains_key(&id) {
+            let annotations = self
+                .results
+                .as_ref()
+                .map(|results| Annotations::new(&results.detections, self.params.class_confidence))
+                .unwrap_or_default();
+            self.edits.insert(id, annotations);
+        }
+        let annotations = self.edits.get_mut(&id).expect("edits were just added");
+        let selected = match &edit {
+            BoxEdit::Add(_) => Some(annotations.boxes().len()),
+            BoxEdit::Reshape(index, _) | BoxEdit::Relabel(index, _) => Some(*index),
+            BoxEdit::Delete(_) => None,
+        };
+        annotations.apply(edit);
+        self.refilter();
+        self.image.set_selected(selected);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(annotations) = self.image_id.and_then(|id| self.edits.get_mut(&id)) {
+            annotations.undo();
+            self.refilter();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(annotations) = self.image_id.and_then(|id| self.edits.get_mut(&id)) {
+            annotations.redo();
+            self.refilter();
+        }
+    }
+
+    /// Hide or show a class, or show one class alone
+    pub fn set_class_filter(&mut self, class_filter: ClassFilter) {
+        self.image.set_class_filter(class_filter.clone());
+        self.class_filter = class_filter;
+    }
+
+    /// Go back to the model's boxes for the current image
+    pub fn discard_edits(&mut self) {
+        if let Some(id) = self.image_id {
+            self.edits.remove(&id);
+            self.refilter();
+        }
+    }
+
+    /// The prompts with blank entries left out
+    pub fn class_names(&self) -> Vec<String> {
+        self.params
+            .class_names
+            .iter()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// The classes to export detections with, the model's own for closed-set models
+    pub fn export_class_names(&self) -> Vec<String> {
+        match self
+            .selected_model
+            .as_ref()
+            .and_then(|model| model.class_names())
+        {
+            Some(names) => names.iter().map(|name| name.to_string()).collect(),
+            None => self.class_names(),
+        }
+    }
+
+    /// What the selected model can do, assuming an open-vocabulary detector if none is selected
+    pub fn capabilities(&self) -> Capabilities {
+        self.selected_model
+            .as_ref()
+            .unwrap_or(&backend::ModelType::GroundingDINO)
+            .capabilities()
+    }
+
+    /// Indices of the boxes of shown classes, in the order of the detection table
+    pub fn sorted_detections(&self) -> Vec<usize> {
+        let boxes = self.image.boxes();
+        let mut order = (0..boxes.len())
+            .filter(|&index| self.image.is_shown(index))
+            .collect::<Vec<_>>();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (&boxes[a], &boxes[b]);
+            let ordering = match self.sort.key {
+                SortKey::Class => a.class.cmp(&b.class),
+                SortKey::Confidence => a.confidence.total_cmp(&b.confidence),
+                SortKey::Area => (a.width * a.height).total_cmp(&(b.width * b.height)),
+            };
+            if self.sort.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        order
+    }
+
+    /// Whether output for `request` is still wanted
+    pub fn is_current(&self, request: backend::RequestId) -> bool {
+        self.request.is_some_and(|current| current.id == request)
+    }
+
+    /// The device detections run on, as far as we know
+    pub fn device(&self) -> backend::Device {
+        self.active_device.unwrap_or(self.params.device)
+    }
+
+    pub fn record_timings(&mut self, timings: Timings) {
+        const HISTORY: usize = 20;
+
+        log::debug!("Detection timings: {timings:?}");
+        self.last_timings = Some(timings);
+        if let Some(model) = self.selected_model.clone() {
+            let history = self
+                .timing_history
+                .entry((model, self.device()))
+                .or_default();
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(timings);
+        }
+    }
+
+    pub fn clear_results(&mut self) {
+        self.image.set_detections(Detections::default());
+        self.results = None;
+    }
+}
+
 impl Default for InferenceState {
     fn default() -> Self {
         Self {
             selecting_image: false,
             selected_model: None,
+            models: vec![],
             busy: false,
-            // detections: vec![],
+            request: None,
+            progress: None,
+            results: None,
+            params: backend::DetectionParams::default(),
+            error: None,
+            notice: None,
+            active_device: None,
+            loading_model: None,
+            load_ms: None,
+            lazy_loading: false,
+            show_hud: false,
+            show_annotated: false,
+            sort: DetectionSort::default(),
+            last_timings: None,
+            timing_history: HashMap::new(),
+            autosave_dir: None,
+            edits: HashMap::new(),
+            image_id: None,
+            editing: false,
+            class_filter: ClassFilter::default(),
+            post_processing_form: PostProcessingForm::default(),
+            params_edited: false,
             image: Image::default(),
         }
     }
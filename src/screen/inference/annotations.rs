@@ -0,0 +1,138 @@
+//! Boxes corrected by hand, kept apart from the raw model output so thresholds and re-runs
+//! don't overwrite them
+
+use iced::Rectangle;
+
+use crate::model::{BoundingBox, Detections};
+
+/// A single change to the boxes of an image
+#[derive(Debug, Clone)]
+pub enum BoxEdit {
+    Add(BoundingBox),
+    /// Move or resize a box, in image pixel coordinates
+    Reshape(usize, Rectangle),
+    Relabel(usize, String),
+    Delete(usize),
+}
+
+/// The boxes, and for each the index of the model's box it started from
+type Snapshot = (Vec<BoundingBox>, Vec<Option<usize>>);
+
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    boxes: Vec<BoundingBox>,
+    /// Index of each box in the model's detections, `None` for boxes drawn by hand
+    sources: Vec<Option<usize>>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+impl Annotations {
+    /// Start editing from the model's boxes with at least the given confidence
+    pub fn new(detections: &Detections, confidence: f32) -> Self {
+        let (sources, boxes) = detections
+            .boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, bbox)| bbox.confidence >= confidence)
+            .map(|(index, bbox)| (Some(index), bbox.clone()))
+            .unzip();
+        Self {
+            boxes,
+            sources,
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+
+    pub fn boxes(&self) -> &[BoundingBox] {
+        &self.boxes
+    }
+
+    /// The items of the model's detections that go with the remaining boxes, if there is one
+    /// per box of `detections`. Boxes drawn by hand have none, and they all come after the rest.
+    pub fn aligned<T: Clone>(&self, items: &[T], detections: &Detections) -> Option<Vec<T>> {
+        (items.len() == detections.boxes.len()).then(|| {
+            self.sources
+                .iter()
+                .flatten()
+                .filter_map(|&index| items.get(index).cloned())
+                .collect()
+        })
+    }
+
+    /// Keep the boxes when the model's detections they started from are replaced, without the
+    /// polygons, masks and keypoints of the old ones
+    pub fn detach(&mut self) {
+        let snapshots = self.undo.iter_mut().chain(&mut self.redo);
+        for sources in
+            std::iter::once(&mut self.sources).chain(snapshots.map(|(_, sources)| sources))
+        {
+            sources.fill(None);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        (self.boxes.clone(), self.sources.clone())
+    }
+
+    fn restore(&mut self, (boxes, sources): Snapshot) -> Snapshot {
+        let before = self.snapshot();
+        self.boxes = boxes;
+        self.sources = sources;
+        before
+    }
+
+    pub fn apply(&mut self, edit: BoxEdit) {
+        let before = self.snapshot();
+        match edit {
+            BoxEdit::Add(bbox) => {
+                self.boxes.push(bbox);
+                self.sources.push(None);
+            }
+            BoxEdit::Reshape(index, rect) => {
+                if let Some(bbox) = self.boxes.get_mut(index) {
+                    bbox.x = rect.x;
+                    bbox.y = rect.y;
+                    bbox.width = rect.width;
+                    bbox.height = rect.height;
+                }
+            }
+            BoxEdit::Relabel(index, class) => {
+                if let Some(bbox) = self.boxes.get_mut(index) {
+                    bbox.class = class;
+                }
+            }
+            BoxEdit::Delete(index) => {
+                if index < self.boxes.len() {
+                    self.boxes.remove(index);
+                    self.sources.remove(index);
+                }
+            }
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(snapshot) = self.undo.pop() {
+            let after = self.restore(snapshot);
+            self.redo.push(after);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(snapshot) = self.redo.pop() {
+            let before = self.restore(snapshot);
+            self.undo.push(before);
+        }
+    }
+}